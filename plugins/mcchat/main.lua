@@ -1,9 +1,8 @@
 local plugin = {
-    id = "mcchat",
-    name = "MCChat",
-    description = "Provides Minecraft-style chat. Messages sent by one client will be broadcasted to every client.",
-    authors = { "trimill" },
-    version = "0.1.0",
+    defaultConfig = {
+        -- announce players joining and leaving in chat
+        joinMessages = true,
+    },
 }
 
 local logger = nil
@@ -13,30 +12,39 @@ function plugin.init()
     logger.info("MCChat version " .. plugin.version)
 end
 
-function plugin.playerJoin(name)
-    logger.info(name .. " joined the game")
+function plugin.playerJoin(player, replay)
+    if replay then
+        return
+    end
+    logger.info(player.name .. " joined the game")
+    if not plugin.config.joinMessages then
+        return
+    end
     server.broadcast({
         translate = "multiplayer.player.joined",
-        with = { {text = name} }, 
+        with = { {text = player.name} },
         color = "yellow"
     })
 end
 
-function plugin.playerLeave(name)
-    logger.info(name .. " left the game")
+function plugin.playerLeave(player)
+    logger.info(player.name .. " left the game")
+    if not plugin.config.joinMessages then
+        return
+    end
     server.broadcast({
         translate = "multiplayer.player.left",
-        with = { {text = name} }, 
+        with = { {text = player.name} },
         color = "yellow"
     })
 end
 
 function plugin.chatMessage(message, author)
-    logger.info("<" .. author .. "> " .. message)
+    logger.info("<" .. author.name .. "> " .. message)
     server.broadcast({
         translate = "chat.type.text",
         with = {
-            {text = author},
+            {text = author.name},
             {text = message}
         }
     })
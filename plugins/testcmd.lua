@@ -16,12 +16,12 @@ function plugin.registerCommands(registry)
     registry.addCommand("test")
 end
 
-function plugin.command(command, args, name, uuid)
-    logger.info("player " .. name .. " ran /" .. command .. " " .. args)
+function plugin.command(command, args, player)
+    logger.info("player " .. player.name .. " ran /" .. command .. " " .. args)
 end
 
-function plugin.playerJoin(name, uuid)
-    logger.info("player joined: " .. name .. " with uuid " .. uuid)
+function plugin.playerJoin(player)
+    logger.info("player joined: " .. player.name .. " with uuid " .. player.uuid)
 end
 
 return plugin
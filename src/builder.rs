@@ -0,0 +1,229 @@
+use std::{path::PathBuf, rc::Rc, sync::mpsc::Receiver, time::{Duration, Instant}};
+
+use log::{info, warn};
+use mlua::Lua;
+
+use crate::{config::{Config, LoginMode, WorldSettings}, console, network::NetworkServer, plugins::{Plugin, Plugins}, protocol::registry::RegistryCodec, world::{AnvilWorld, BlockRegistry, FlatGenerator, Schematic, LightTable, World, WorldRegistry}, VERSION};
+
+/// Sets up and runs a server.
+///
+/// ```no_run
+/// use quectocraft::{Config, ServerBuilder};
+///
+/// ServerBuilder::new()
+///     .config(Config { port: 25570, ..Config::default() })
+///     .plugin_dir("test-plugins")
+///     .run()
+///     .unwrap();
+/// ```
+#[derive(Default)]
+pub struct ServerBuilder {
+    config: Config,
+    plugin_dir: Option<PathBuf>,
+    data_dir: Option<PathBuf>,
+    natives: Vec<Box<dyn Plugin>>,
+    commands: Option<Receiver<String>>,
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Load plugins from this directory instead of the one in the config.
+    pub fn plugin_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.plugin_dir = Some(dir.into());
+        self
+    }
+
+    /// Keep plugin storage in this directory instead of the one in the config.
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(dir.into());
+        self
+    }
+
+    /// Add a plugin written in Rust.
+    pub fn plugin(mut self, plugin: impl Plugin + 'static) -> Self {
+        self.natives.push(Box::new(plugin));
+        self
+    }
+
+    /// Read console commands (such as `reload` and `stop`) from this channel
+    /// instead of standard input. No Ctrl-C handler is installed either.
+    pub fn commands(mut self, commands: Receiver<String>) -> Self {
+        self.commands = Some(commands);
+        self
+    }
+
+    /// Run the server until it is stopped with the `stop` console command.
+    pub fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = self.config;
+        if let Some(dir) = self.plugin_dir {
+            config.plugin_dir = dir;
+        }
+        if let Some(dir) = self.data_dir {
+            config.data_dir = dir;
+        }
+
+        info!("Starting Quectocraft version {}", VERSION);
+        match config.login {
+            LoginMode::Offline => warn!("Running in offline mode!"),
+            LoginMode::Velocity => info!("Running in velocity mode"),
+        }
+
+        let lua = Lua::new();
+        let mut plugins = Plugins::new(&lua, &config)?;
+        std::fs::create_dir_all(&config.plugin_dir)
+            .map_err(|e| format!("couldn't create the plugins directory: {}", e))?;
+        for plugin in self.natives {
+            plugins.register_boxed(plugin);
+        }
+        plugins.load_plugins();
+
+        let codec = registry_codec(&config)?;
+        let worlds = load_worlds(&config, &codec)?;
+        let mut server = NetworkServer::new(config, plugins, worlds, &codec);
+        let console = self.commands.unwrap_or_else(console::spawn);
+        let sleep_dur = Duration::from_millis(5);
+        let tick_dur = Duration::from_millis(50);
+        let mut next_tick = Instant::now();
+        let mut i = 0;
+        loop {
+            server.get_new_clients();
+            server.handle_connections();
+            while let Ok(line) = console.try_recv() {
+                server.handle_console(&line);
+            }
+            if Instant::now() >= next_tick {
+                server.tick();
+                next_tick = Instant::now() + tick_dur;
+            }
+            if server.stop_requested() {
+                server.shutdown();
+                return Ok(())
+            }
+            if i % 200 == 0 {
+                server.check_plugin_changes();
+            }
+            if i % 1024 == 0 {
+                server.send_keep_alive();
+                server.save();
+                i = 0;
+            }
+            i += 1;
+            std::thread::sleep(sleep_dur);
+        }
+    }
+}
+
+/// The vanilla registry codec with the configured dimension types and
+/// biomes added or changed.
+fn registry_codec(config: &Config) -> Result<RegistryCodec, Box<dyn std::error::Error>> {
+    let mut codec = RegistryCodec::vanilla();
+    for (name, settings) in &config.dimension_types {
+        let base = settings.base.as_deref().unwrap_or("minecraft:overworld");
+        let mut dimension = match codec.dimension_types.get(name).filter(|_| settings.base.is_none()) {
+            Some(dimension) => dimension.clone(),
+            None => codec.dimension_types.get(base).cloned()
+                .ok_or_else(|| format!("dimension type '{}': unknown base '{}'", name, base))?,
+        };
+        if let Some(v) = settings.ambient_light { dimension.ambient_light = v }
+        if let Some(v) = settings.fixed_time { dimension.fixed_time = Some(v) }
+        if let Some(v) = &settings.effects { dimension.effects = v.clone() }
+        if let Some(v) = settings.has_skylight { dimension.has_skylight = v }
+        if let Some(v) = settings.has_ceiling { dimension.has_ceiling = v }
+        if let Some(v) = settings.min_y { dimension.min_y = v }
+        if let Some(v) = settings.height {
+            dimension.height = v;
+            dimension.logical_height = dimension.logical_height.min(v);
+        }
+        if let Some(v) = settings.logical_height { dimension.logical_height = v }
+        dimension.check().map_err(|e| format!("dimension type '{}': {}", name, e))?;
+        codec.dimension_types.insert(name, dimension);
+    }
+    for (name, settings) in &config.biomes {
+        let base = settings.base.as_deref().unwrap_or("minecraft:plains");
+        let mut biome = match codec.biomes.get(name).filter(|_| settings.base.is_none()) {
+            Some(biome) => biome.clone(),
+            None => codec.biomes.get(base).cloned()
+                .ok_or_else(|| format!("biome '{}': unknown base '{}'", name, base))?,
+        };
+        if let Some(v) = &settings.precipitation { biome.precipitation = v.clone() }
+        if let Some(v) = settings.temperature { biome.temperature = v }
+        if let Some(v) = settings.downfall { biome.downfall = v }
+        if let Some(v) = settings.sky_color { biome.effects.sky_color = v }
+        if let Some(v) = settings.fog_color { biome.effects.fog_color = v }
+        if let Some(v) = settings.water_color { biome.effects.water_color = v }
+        if let Some(v) = settings.water_fog_color { biome.effects.water_fog_color = v }
+        if let Some(v) = settings.grass_color { biome.effects.grass_color = Some(v) }
+        if let Some(v) = settings.foliage_color { biome.effects.foliage_color = Some(v) }
+        codec.biomes.insert(name, biome);
+    }
+    Ok(codec)
+}
+
+/// Build the main world and any others that are configured.
+fn load_worlds(config: &Config, codec: &RegistryCodec) -> Result<WorldRegistry, Box<dyn std::error::Error>> {
+    let registry = match &config.block_report {
+        Some(report) => BlockRegistry::load_report(report)?,
+        None => BlockRegistry::builtin(),
+    };
+    let light = Rc::new(LightTable::new(&registry));
+    let mut worlds = WorldRegistry::new();
+    worlds.insert(&config.world_name, load_world(&config.main_world(), &registry, &light, codec)?);
+    for (name, settings) in &config.worlds {
+        let world = load_world(settings, &registry, &light, codec)
+            .map_err(|e| format!("world '{}': {}", name, e))?;
+        worlds.insert(name, world);
+    }
+    worlds.set_blocks(registry);
+    Ok(worlds)
+}
+
+/// Build a world from its vanilla world, generator and schematic, if any,
+/// with the height of its dimension type.
+fn load_world(settings: &WorldSettings, registry: &BlockRegistry, light: &Rc<LightTable>, codec: &RegistryCodec) -> Result<World, Box<dyn std::error::Error>> {
+    let biome = codec.biomes.id(&settings.biome)
+        .ok_or_else(|| format!("unknown biome '{}'", settings.biome))? as u32;
+    let layers = FlatGenerator::parse_layers(&settings.layers, registry)
+        .map_err(|e| format!("invalid layers: {}", e))?;
+
+    let dimension_type = match &settings.dimension_type {
+        Some(name) => name.as_str(),
+        None if settings.world.is_some() || !layers.is_empty() => "minecraft:overworld",
+        None => "minecraft:the_end",
+    };
+    let dimension = codec.dimension_types.get(dimension_type)
+        .ok_or_else(|| format!("unknown dimension type '{}'", dimension_type))?;
+    let mut world = World::new(dimension_type, dimension.min_y, dimension.height as u32);
+    world.set_lighting(light.clone(), dimension.has_skylight);
+
+    if let Some(dir) = &settings.world {
+        let (x, y, z) = AnvilWorld::spawn(dir)?;
+        world = world.with_source(AnvilWorld::open(dir, registry.clone(), codec.biomes.ids())?);
+        world.set_spawn(x, y, z);
+        info!("Serving world {}", dir.display());
+    } else if !layers.is_empty() {
+        let radius = Some(settings.platform_radius).filter(|r| *r > 0);
+        let generator = FlatGenerator::new(layers, radius, biome);
+        world.set_spawn(0, world.min_y() + generator.height() as i32, 0);
+        world = world.with_source(generator);
+    }
+    world.set_biome(biome);
+    world.set_biome_count(codec.biomes.value.len());
+    if let Some(y) = settings.spawn_height {
+        let (x, _, z) = world.spawn();
+        world.set_spawn(x, y, z);
+    }
+    if let Some(path) = &settings.schematic {
+        let schematic = Schematic::load(path, registry)?;
+        schematic.place(&mut world, settings.schematic_offset);
+        info!("Loaded schematic {} ({}x{}x{})", path.display(), schematic.width, schematic.height, schematic.length);
+    }
+    Ok(world)
+}
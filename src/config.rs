@@ -1,8 +1,9 @@
-use std::{net::IpAddr, fs::OpenOptions};
+use std::{collections::{BTreeMap, HashMap}, net::{IpAddr, Ipv4Addr}, fs, path::{Path, PathBuf}, fmt, str::FromStr};
 
+use log::warn;
 use serde::Deserialize;
 
-#[derive(Deserialize, PartialEq, Eq)]
+#[derive(Deserialize, PartialEq, Eq, Debug)]
 #[serde(rename_all="lowercase")]
 pub enum LoginMode {
     Offline,
@@ -10,18 +11,713 @@ pub enum LoginMode {
     // TODO online, bungeecord
 }
 
-#[derive(Deserialize)]
+/// What players can do in the world.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all="lowercase")]
+pub enum GameMode {
+    Survival,
+    Creative,
+    Adventure,
+    Spectator,
+}
+
+impl GameMode {
+    /// The ID used in packets.
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Survival => "survival",
+            Self::Creative => "creative",
+            Self::Adventure => "adventure",
+            Self::Spectator => "spectator",
+        }
+    }
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+#[serde(default)]
 pub struct Config {
     pub addr: IpAddr,
     pub port: u16,
     pub login: LoginMode,
     pub velocity_secret: Option<String>,
+    /// Directory plugins are loaded from.
+    pub plugin_dir: PathBuf,
+    /// Directory plugin storage is kept in.
+    pub data_dir: PathBuf,
+    /// Names or UUIDs of players allowed to use `/qc` admin commands.
+    pub operators: Vec<String>,
+    /// Reload plugins automatically when files in the plugins directory change.
+    pub watch_plugins: bool,
+    /// How long a single plugin event handler may run for, in milliseconds.
+    pub plugin_time_limit: u64,
+    /// Disable a plugin after this many consecutive errors, or never if 0.
+    pub plugin_max_failures: u32,
+    /// How many chunks around them players are sent.
+    pub view_distance: u8,
+    /// Name of the world players join, which is set up from the fields
+    /// below.
+    pub world_name: String,
+    /// Position players spawn at, instead of the world's spawn point.
+    pub spawn: Option<[f64; 3]>,
+    /// Direction players face when they spawn, in degrees.
+    pub spawn_yaw: f32,
+    pub spawn_pitch: f32,
+    /// Game mode players join in.
+    pub gamemode: GameMode,
+    /// Show the hardcore hearts and death screen.
+    pub hardcore: bool,
+    /// Hide coordinates and other details from the debug screen.
+    pub reduced_debug_info: bool,
+    /// Show the respawn screen when a player dies, rather than respawning
+    /// them immediately.
+    pub respawn_screen: bool,
+    /// Vanilla world folder to serve, read only.
+    pub world: Option<PathBuf>,
+    /// Layers of blocks to generate the world from, bottom first, such as
+    /// `bedrock,2*dirt,grass_block`.
+    pub layers: String,
+    /// Only generate layers this many blocks around 0, 0, or everywhere if 0.
+    pub platform_radius: u32,
+    /// Biome of generated and empty chunks.
+    pub biome: String,
+    /// Dimension type of the world. By default `minecraft:overworld` for a
+    /// vanilla world or generated layers, and `minecraft:the_end` otherwise.
+    pub dimension_type: Option<String>,
+    /// Dimension types to add to the registry codec or change, by name.
+    pub dimension_types: HashMap<String, DimensionSettings>,
+    /// Biomes to add to the registry codec or change, by name.
+    pub biomes: HashMap<String, BiomeSettings>,
+    /// Other worlds players can be moved to by plugins, by name.
+    pub worlds: BTreeMap<String, WorldSettings>,
+    /// Height players spawn at, by default just above the ground.
+    pub spawn_height: Option<i32>,
+    /// Sponge schematic to build the world from.
+    pub schematic: Option<PathBuf>,
+    /// Where the schematic's minimum corner is placed.
+    pub schematic_offset: [i32; 3],
+    /// Vanilla `blocks.json` report used to map block names to states,
+    /// instead of the small built-in table.
+    pub block_report: Option<PathBuf>,
+    /// Fuel (roughly instructions) a WebAssembly plugin handler may use.
+    #[cfg(feature = "wasm")]
+    pub wasm_fuel: u64,
+    /// Maximum memory a WebAssembly plugin may use, in bytes.
+    #[cfg(feature = "wasm")]
+    pub wasm_memory_limit: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 25565,
+            login: LoginMode::Offline,
+            velocity_secret: None,
+            plugin_dir: PathBuf::from("plugins"),
+            data_dir: PathBuf::from("data"),
+            operators: Vec::new(),
+            watch_plugins: false,
+            plugin_time_limit: 250,
+            plugin_max_failures: 10,
+            view_distance: 8,
+            world_name: "qc:world".to_owned(),
+            spawn: None,
+            spawn_yaw: 0.0,
+            spawn_pitch: 0.0,
+            gamemode: GameMode::Spectator,
+            hardcore: false,
+            reduced_debug_info: false,
+            respawn_screen: false,
+            world: None,
+            layers: String::new(),
+            platform_radius: 0,
+            biome: "minecraft:plains".to_owned(),
+            dimension_type: None,
+            dimension_types: HashMap::new(),
+            biomes: HashMap::new(),
+            worlds: BTreeMap::new(),
+            spawn_height: None,
+            schematic: None,
+            schematic_offset: [0, 0, 0],
+            block_report: None,
+            #[cfg(feature = "wasm")]
+            wasm_fuel: 10_000_000,
+            #[cfg(feature = "wasm")]
+            wasm_memory_limit: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Where the blocks of a world come from, and its dimension type. The
+/// fields mean the same as the config fields with the same names.
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldSettings {
+    pub world: Option<PathBuf>,
+    pub layers: String,
+    pub platform_radius: u32,
+    pub biome: String,
+    pub dimension_type: Option<String>,
+    pub spawn_height: Option<i32>,
+    pub schematic: Option<PathBuf>,
+    pub schematic_offset: [i32; 3],
+}
+
+impl Default for WorldSettings {
+    fn default() -> Self {
+        Self {
+            world: None,
+            layers: String::new(),
+            platform_radius: 0,
+            biome: "minecraft:plains".to_owned(),
+            dimension_type: None,
+            spawn_height: None,
+            schematic: None,
+            schematic_offset: [0, 0, 0],
+        }
+    }
+}
+
+/// Changes to a dimension type. A dimension type that doesn't exist yet is
+/// copied from `base` first.
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DimensionSettings {
+    /// Dimension type to copy, `minecraft:overworld` if not set.
+    pub base: Option<String>,
+    pub ambient_light: Option<f32>,
+    pub fixed_time: Option<i64>,
+    pub effects: Option<String>,
+    pub has_skylight: Option<bool>,
+    pub has_ceiling: Option<bool>,
+    pub min_y: Option<i32>,
+    pub height: Option<i32>,
+    pub logical_height: Option<i32>,
+}
+
+/// Changes to a biome. A biome that doesn't exist yet is copied from
+/// `base` first.
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct BiomeSettings {
+    /// Biome to copy, `minecraft:plains` if not set.
+    pub base: Option<String>,
+    pub precipitation: Option<String>,
+    pub temperature: Option<f32>,
+    pub downfall: Option<f32>,
+    pub sky_color: Option<i32>,
+    pub fog_color: Option<i32>,
+    pub water_color: Option<i32>,
+    pub water_fog_color: Option<i32>,
+    pub grass_color: Option<i32>,
+    pub foliage_color: Option<i32>,
+}
+
+/// A config value that couldn't be used, and the field it was for.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub field: String,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(field: &str, message: impl Into<String>) -> Self {
+        Self { field: field.to_owned(), message: message.into() }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid config field '{}': {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl FromStr for LoginMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "offline" => Ok(Self::Offline),
+            "velocity" => Ok(Self::Velocity),
+            _ => Err(format!("unknown login mode '{}', expected offline or velocity", s)),
+        }
+    }
+}
+
+impl FromStr for GameMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "survival" | "0" => Ok(Self::Survival),
+            "creative" | "1" => Ok(Self::Creative),
+            "adventure" | "2" => Ok(Self::Adventure),
+            "spectator" | "3" => Ok(Self::Spectator),
+            _ => Err(format!("unknown game mode '{}', expected survival, creative, adventure or spectator", s)),
+        }
+    }
+}
+
+fn parse<T: FromStr>(field: &str, value: &str) -> Result<T, ConfigError>
+where T::Err: fmt::Display {
+    value.trim().parse().map_err(|e| ConfigError::new(field, format!("'{}': {}", value, e)))
+}
+
+fn parse_bool(field: &str, value: &str) -> Result<bool, ConfigError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(ConfigError::new(field, format!("'{}': expected true or false", value))),
+    }
+}
+
+/// Parse comma-separated `x,y,z` coordinates.
+fn parse_coords<T: FromStr>(field: &str, value: &str) -> Result<[T; 3], ConfigError>
+where T::Err: fmt::Display {
+    let coords: Vec<T> = value.split(',')
+        .map(|c| parse(field, c))
+        .collect::<Result<_, _>>()?;
+    coords.try_into()
+        .map_err(|_| ConfigError::new(field, format!("'{}': expected x,y,z", value)))
+}
+
+/// Whether `name` is a resource location such as `qc:world`.
+fn valid_identifier(name: &str) -> bool {
+    let (namespace, path) = name.split_once(':').unwrap_or(("minecraft", name));
+    let allowed = |c: char, extra: &str| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-.".contains(c) || extra.contains(c);
+    !namespace.is_empty() && !path.is_empty()
+        && namespace.chars().all(|c| allowed(c, ""))
+        && path.chars().all(|c| allowed(c, "/"))
+}
+
+impl Config {
+    /// Names of the fields that can be set with [`Config::set`].
+    pub const FIELDS: &'static [&'static str] = &[
+        "addr", "port", "login", "velocity_secret", "plugin_dir", "data_dir",
+        "operators", "watch_plugins", "plugin_time_limit", "plugin_max_failures",
+        "view_distance", "spawn", "spawn_yaw", "spawn_pitch", "gamemode", "hardcore",
+        "reduced_debug_info", "respawn_screen", "world_name", "world", "layers", "platform_radius", "biome", "dimension_type", "spawn_height",
+        "schematic", "schematic_offset", "block_report",
+        #[cfg(feature = "wasm")]
+        "wasm_fuel",
+        #[cfg(feature = "wasm")]
+        "wasm_memory_limit",
+    ];
+
+    /// Fields that are tables, and so can only be set in the config file.
+    pub const TABLES: &'static [&'static str] = &["dimension_types", "biomes", "worlds"];
+
+    /// Set a field from its string form, as given on the command line or in
+    /// an environment variable. Lists and coordinates are comma-separated,
+    /// and an empty value unsets an optional field.
+    pub fn set(&mut self, field: &str, value: &str) -> Result<(), ConfigError> {
+        match field {
+            "addr" => self.addr = parse(field, value)?,
+            "port" => self.port = parse(field, value)?,
+            "login" => self.login = parse(field, value)?,
+            "velocity_secret" => self.velocity_secret = Some(value.to_owned()).filter(|s| !s.is_empty()),
+            "plugin_dir" => self.plugin_dir = PathBuf::from(value),
+            "data_dir" => self.data_dir = PathBuf::from(value),
+            "operators" => self.operators = value.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            "watch_plugins" => self.watch_plugins = parse_bool(field, value)?,
+            "plugin_time_limit" => self.plugin_time_limit = parse(field, value)?,
+            "plugin_max_failures" => self.plugin_max_failures = parse(field, value)?,
+            "view_distance" => self.view_distance = parse(field, value)?,
+            "spawn" => self.spawn = match value.trim() {
+                "" => None,
+                v => Some(parse_coords(field, v)?),
+            },
+            "spawn_yaw" => self.spawn_yaw = parse(field, value)?,
+            "spawn_pitch" => self.spawn_pitch = parse(field, value)?,
+            "gamemode" => self.gamemode = parse(field, value)?,
+            "hardcore" => self.hardcore = parse_bool(field, value)?,
+            "reduced_debug_info" => self.reduced_debug_info = parse_bool(field, value)?,
+            "respawn_screen" => self.respawn_screen = parse_bool(field, value)?,
+            "world_name" => self.world_name = value.trim().to_owned(),
+            "world" => self.world = Some(PathBuf::from(value)).filter(|p| !p.as_os_str().is_empty()),
+            "layers" => self.layers = value.to_owned(),
+            "platform_radius" => self.platform_radius = parse(field, value)?,
+            "biome" => self.biome = value.to_owned(),
+            "dimension_type" => self.dimension_type = Some(value.trim().to_owned()).filter(|s| !s.is_empty()),
+            "spawn_height" => self.spawn_height = match value.trim() {
+                "" => None,
+                v => Some(parse(field, v)?),
+            },
+            "schematic" => self.schematic = Some(PathBuf::from(value)).filter(|p| !p.as_os_str().is_empty()),
+            "schematic_offset" => self.schematic_offset = parse_coords(field, value)?,
+            "block_report" => self.block_report = Some(PathBuf::from(value)).filter(|p| !p.as_os_str().is_empty()),
+            #[cfg(feature = "wasm")]
+            "wasm_fuel" => self.wasm_fuel = parse(field, value)?,
+            #[cfg(feature = "wasm")]
+            "wasm_memory_limit" => self.wasm_memory_limit = parse(field, value)?,
+            _ => return Err(ConfigError::new(field, "no such field")),
+        }
+        Ok(())
+    }
+
+    /// Override fields from `QC_<FIELD>` environment variables, such as
+    /// `QC_PORT` or `QC_PLUGIN_DIR`.
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        for field in Self::FIELDS {
+            let var = format!("QC_{}", field.to_ascii_uppercase());
+            match std::env::var(&var) {
+                Ok(value) => self.set(field, &value)
+                    .map_err(|e| ConfigError::new(field, format!("{} (from {})", e.message, var)))?,
+                Err(std::env::VarError::NotPresent) => (),
+                Err(e) => return Err(ConfigError::new(field, format!("{}: {}", var, e))),
+            }
+        }
+        Ok(())
+    }
+
+    /// The settings of the main world, taken from the top-level fields.
+    pub fn main_world(&self) -> WorldSettings {
+        WorldSettings {
+            world: self.world.clone(),
+            layers: self.layers.clone(),
+            platform_radius: self.platform_radius,
+            biome: self.biome.clone(),
+            dimension_type: self.dimension_type.clone(),
+            spawn_height: self.spawn_height,
+            schematic: self.schematic.clone(),
+            schematic_offset: self.schematic_offset,
+        }
+    }
+
+    /// Check that the fields make sense together.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.login == LoginMode::Velocity && self.velocity_secret.is_none() {
+            return Err(ConfigError::new("velocity_secret", "velocity login is enabled but no secret is configured"))
+        }
+        if !(2..=32).contains(&self.view_distance) {
+            return Err(ConfigError::new("view_distance", "must be between 2 and 32"))
+        }
+        if !(-90.0..=90.0).contains(&self.spawn_pitch) {
+            return Err(ConfigError::new("spawn_pitch", "must be between -90 and 90"))
+        }
+        if self.world.is_some() && !self.layers.trim().is_empty() {
+            return Err(ConfigError::new("layers", "can't generate layers when a vanilla world is set"))
+        }
+        if self.world.is_some() && self.block_report.is_none() {
+            return Err(ConfigError::new("block_report", "must be set to load a vanilla world"))
+        }
+        if self.schematic.is_some() && self.block_report.is_none() {
+            return Err(ConfigError::new("block_report", "must be set to load a schematic"))
+        }
+        if !valid_identifier(&self.world_name) {
+            return Err(ConfigError::new("world_name", format!("'{}' is not a valid name, such as qc:world", self.world_name)))
+        }
+        for (name, world) in &self.worlds {
+            let field = format!("worlds.{}", name);
+            if !valid_identifier(name) {
+                return Err(ConfigError::new(&field, format!("'{}' is not a valid name, such as qc:lobby", name)))
+            }
+            if *name == self.world_name {
+                return Err(ConfigError::new(&field, "has the same name as the main world"))
+            }
+            if world.world.is_some() && !world.layers.trim().is_empty() {
+                return Err(ConfigError::new(&field, "can't generate layers when a vanilla world is set"))
+            }
+            if world.world.is_some() && self.block_report.is_none() {
+                return Err(ConfigError::new("block_report", format!("must be set to load the vanilla world in {}", field)))
+            }
+            if world.schematic.is_some() && self.block_report.is_none() {
+                return Err(ConfigError::new("block_report", format!("must be set to load the schematic in {}", field)))
+            }
+        }
+        if self.plugin_time_limit == 0 {
+            return Err(ConfigError::new("plugin_time_limit", "must be greater than 0"))
+        }
+        Ok(())
+    }
+}
+
+/// The config file written on first start. Keep in sync with `Config::default`.
+pub const DEFAULT_CONFIG: &str = r#"# Quectocraft configuration. Every field is optional; the values below are
+# the defaults. Any field can also be set with a QC_<FIELD> environment
+# variable, e.g. QC_PORT=25570.
+
+# Address and port to listen on.
+addr = "127.0.0.1"
+port = 25565
+
+# How players are authenticated: "offline" or "velocity".
+login = "offline"
+# Forwarding secret shared with Velocity, required in velocity mode.
+# velocity_secret = ""
+
+# Directory plugins are loaded from.
+plugin_dir = "plugins"
+# Directory plugin storage is kept in.
+data_dir = "data"
+
+# Names or UUIDs of players allowed to use /qc admin commands.
+operators = []
+
+# Reload plugins automatically when files in the plugins directory change.
+watch_plugins = false
+
+# How long a single plugin event handler may run for, in milliseconds.
+plugin_time_limit = 250
+# Disable a plugin after this many consecutive errors, or never if 0.
+plugin_max_failures = 10
+
+# How many chunks around them players are sent, from 2 to 32.
+view_distance = 8
+
+# Where players spawn, instead of the world's spawn point, and the direction
+# they face (yaw 0 is south, pitch -90 is straight up).
+# spawn = [0.5, 64.0, 0.5]
+spawn_yaw = 0.0
+spawn_pitch = 0.0
+# Game mode players join in: "survival", "creative", "adventure" or
+# "spectator".
+gamemode = "spectator"
+# Show hardcore hearts.
+hardcore = false
+# Hide coordinates and other details from the F3 debug screen.
+reduced_debug_info = false
+# Show the respawn screen when a player dies instead of respawning them
+# immediately.
+respawn_screen = false
+
+# Name of the world players join, which is set up from the fields below.
+world_name = "qc:world"
+# Vanilla world folder (1.18 or later) to serve read only, starting at its
+# spawn point. Without it players spawn in an empty world.
+# world = "world"
+# Generate a flat world from these layers of blocks, bottom first, e.g.
+# "bedrock,2*dirt,grass_block". Set platform_radius to only generate a round
+# platform that many blocks around 0, 0 instead of an endless world.
+layers = ""
+platform_radius = 0
+# Biome of generated and empty chunks.
+biome = "minecraft:plains"
+# Dimension type of the world, which sets its height, lighting and sky. By
+# default "minecraft:overworld" for a vanilla world or generated layers, and
+# "minecraft:the_end" (no sky or fog) otherwise.
+# dimension_type = "minecraft:overworld"
+# Height players spawn at, unless spawn is set. By default this is just
+# above the generated layers, or y=64.
+# spawn_height = 64
+# Sponge schematic (.schem) to build the world from, and where to place
+# its minimum corner. It is placed on top of the vanilla world if both are set.
+# schematic = "limbo.schem"
+schematic_offset = [0, 0, 0]
+# Vanilla blocks.json report (from the 1.19.3 server's data generator) used
+# to map block names to states for both of the above. Required to load a
+# vanilla world or schematic.
+# block_report = "blocks.json"
+
+# Fuel (roughly instructions) a WebAssembly plugin handler may use.
+# wasm_fuel = 10000000
+# Maximum memory a WebAssembly plugin may use, in bytes.
+# wasm_memory_limit = 16777216
+
+# Tables must come after every other field in the file.
+
+# Dimension types can be changed, or new ones added by copying another
+# ("minecraft:overworld" unless base is set):
+# [dimension_types."qc:lobby"]
+# base = "minecraft:overworld"
+# ambient_light = 1.0      # 0 to 1, lights up every block
+# fixed_time = 6000        # stop the sun at noon
+# effects = "minecraft:the_end"
+# has_skylight = true
+# has_ceiling = false
+# min_y = 0                # multiple of 16
+# height = 256             # multiple of 16
+# logical_height = 256
+
+# Biomes can be changed or added in the same way ("minecraft:plains" unless
+# base is set). Colors are 0xRRGGBB.
+# [biomes."qc:void"]
+# base = "minecraft:plains"
+# precipitation = "none"   # none, rain or snow
+# temperature = 0.8
+# downfall = 0.4
+# sky_color = 0x000000
+# fog_color = 0x000000
+# water_color = 0x3f76e4
+# water_fog_color = 0x050533
+# grass_color = 0x91bd59
+# foliage_color = 0x77ab2f
+
+# More worlds, which plugins can move players to with player:setWorld. Each
+# can set world, layers, platform_radius, biome, dimension_type,
+# spawn_height, schematic and schematic_offset, as above.
+# [worlds."qc:arena"]
+# schematic = "arena.schem"
+# dimension_type = "minecraft:overworld"
+"#;
+
+/// A parsed config file.
+pub struct ConfigFile {
+    pub config: Config,
+    /// Top-level keys that aren't config fields, and so were ignored.
+    pub unknown_keys: Vec<String>,
+}
+
+/// Read a TOML or JSON config file, chosen by its extension, without
+/// applying overrides or validating it.
+pub fn read_config(path: impl AsRef<Path>) -> Result<ConfigFile, Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("couldn't open {}: {}", path.display(), e))?;
+    let parse_err = |e: &dyn fmt::Display| format!("couldn't parse {}: {}", path.display(), e);
+    let (config, keys): (Config, Vec<String>) = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => {
+            let value: toml::Table = toml::from_str(&text).map_err(|e| parse_err(&e))?;
+            let keys = value.keys().cloned().collect();
+            (value.try_into().map_err(|e| parse_err(&e))?, keys)
+        }
+        Some("json") => {
+            let value: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&text).map_err(|e| parse_err(&e))?;
+            let keys = value.keys().cloned().collect();
+            (serde_json::from_value(value.into()).map_err(|e| parse_err(&e))?, keys)
+        }
+        _ => Err(format!("{}: config files must end in .toml or .json", path.display()))?,
+    };
+    let unknown_keys = keys.into_iter()
+        .filter(|k| !Config::FIELDS.contains(&k.as_str()) && !Config::TABLES.contains(&k.as_str()))
+        .collect();
+    Ok(ConfigFile { config, unknown_keys })
+}
+
+/// Write the default config file, unless something already exists at `path`.
+pub fn write_default_config(path: impl AsRef<Path>) -> std::io::Result<()> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    fs::OpenOptions::new().write(true).create_new(true).open(path)
+        .and_then(|mut f| std::io::Write::write_all(&mut f, DEFAULT_CONFIG.as_bytes()))
+}
+
+/// Read a config file and apply `QC_*` environment variables, then the
+/// given `(field, value)` overrides (from the command line), without
+/// validating the result.
+pub fn resolve_config(path: impl AsRef<Path>, overrides: &[(&str, String)]) -> Result<ConfigFile, Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let mut file = read_config(path)?;
+    let config = &mut file.config;
+    config.apply_env()
+        .and_then(|_| overrides.iter().try_for_each(|(field, value)| config.set(field, value)))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(file)
+}
+
+/// Read a config file, warn about unknown keys, apply `QC_*` environment
+/// variables and `overrides` and validate it.
+pub fn load_config(path: impl AsRef<Path>, overrides: &[(&str, String)]) -> Result<Config, Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let file = resolve_config(path, overrides)?;
+    for key in &file.unknown_keys {
+        warn!("Unknown key '{}' in {}", key, path.display());
+    }
+    file.config.validate()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(file.config)
 }
 
-pub fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
-    let config: Config = serde_json::from_reader(OpenOptions::new().read(true).open("./config.json")?)?;
-    if config.login == LoginMode::Velocity && config.velocity_secret.is_none() {
-        Err("Velocity is enabled but no secret is configured")?
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_matches_defaults() {
+        let table: toml::Table = toml::from_str(DEFAULT_CONFIG).unwrap();
+        for key in table.keys() {
+            assert!(Config::FIELDS.contains(&key.as_str()), "unknown key '{}' in DEFAULT_CONFIG", key);
+        }
+        let config: Config = table.try_into().unwrap();
+        assert_eq!(config, Config::default());
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn set_fields() {
+        let mut config = Config::default();
+        config.set("operators", "alice, bob,,").unwrap();
+        assert_eq!(config.operators, ["alice", "bob"]);
+        config.set("operators", "").unwrap();
+        assert!(config.operators.is_empty());
+        config.set("spawn", "0.5,64,-3.5").unwrap();
+        assert_eq!(config.spawn, Some([0.5, 64.0, -3.5]));
+        config.set("spawn", "").unwrap();
+        assert_eq!(config.spawn, None);
+        config.set("watch_plugins", "yes").unwrap();
+        assert!(config.watch_plugins);
+        config.set("gamemode", "1").unwrap();
+        assert_eq!(config.gamemode, GameMode::Creative);
+        assert!(config.set("schematic_offset", "1,2").is_err());
+        assert!(config.set("port", "65536").is_err());
+        assert!(config.set("worlds", "qc:lobby").is_err());
+    }
+
+    #[test]
+    fn schematic_needs_block_report() {
+        let mut config = Config { schematic: Some(PathBuf::from("limbo.schem")), ..Default::default() };
+        assert_eq!(config.validate().unwrap_err().field, "block_report");
+        config.block_report = Some(PathBuf::from("blocks.json"));
+        config.validate().unwrap();
+
+        let mut config = Config::default();
+        let arena = WorldSettings { schematic: Some(PathBuf::from("arena.schem")), ..Default::default() };
+        config.worlds.insert("qc:arena".to_owned(), arena);
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.field, "block_report");
+        assert!(error.message.contains("worlds.qc:arena"), "{}", error);
+    }
+
+    #[test]
+    fn world_needs_block_report() {
+        let config = Config { world: Some(PathBuf::from("limbo")), ..Default::default() };
+        assert_eq!(config.validate().unwrap_err().field, "block_report");
+
+        let mut config = Config::default();
+        let lobby = WorldSettings { world: Some(PathBuf::from("lobby")), ..Default::default() };
+        config.worlds.insert("qc:lobby".to_owned(), lobby);
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.field, "block_report");
+        assert!(error.message.contains("worlds.qc:lobby"), "{}", error);
+        config.block_report = Some(PathBuf::from("blocks.json"));
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn env_overrides() {
+        // the only test that touches QC_* variables, so they can't leak into others
+        let vars = [("QC_PORT", "25570"), ("QC_OPERATORS", "alice,bob"), ("QC_DATA_DIR", "/srv/qc"), ("QC_SPAWN_HEIGHT", "80")];
+        for (var, value) in vars {
+            std::env::set_var(var, value);
+        }
+        let mut config = Config::default();
+        let result = config.apply_env();
+        std::env::set_var("QC_VIEW_DISTANCE", "far");
+        let error = Config::default().apply_env().unwrap_err();
+        for (var, _) in vars {
+            std::env::remove_var(var);
+        }
+        std::env::remove_var("QC_VIEW_DISTANCE");
+
+        result.unwrap();
+        assert_eq!(config.port, 25570);
+        assert_eq!(config.operators, ["alice", "bob"]);
+        assert_eq!(config.data_dir, PathBuf::from("/srv/qc"));
+        assert_eq!(config.spawn_height, Some(80));
+        assert_eq!(config.view_distance, 8);
+        assert_eq!(error.field, "view_distance");
+        assert!(error.message.contains("QC_VIEW_DISTANCE"), "{}", error);
     }
-    Ok(config)
 }
@@ -0,0 +1,23 @@
+use std::{io::BufRead, sync::mpsc::{Receiver, channel}, thread};
+
+use log::warn;
+
+/// Read commands from standard input on a background thread. Ctrl-C is
+/// turned into a `stop` command so the server can shut down cleanly.
+pub fn spawn() -> Receiver<String> {
+    let (send, recv) = channel();
+    let send_2 = send.clone();
+    if let Err(e) = ctrlc::set_handler(move || { let _ = send_2.send("stop".to_owned()); }) {
+        warn!("Couldn't set Ctrl-C handler: {}", e);
+    }
+    thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            let line = line.trim();
+            if !line.is_empty() && send.send(line.to_owned()).is_err() {
+                break
+            }
+        }
+    });
+    recv
+}
@@ -0,0 +1,18 @@
+//! Quectocraft, a minimal Minecraft server that does almost everything
+//! through plugins. Use [`ServerBuilder`] to run a server from your own code.
+
+pub mod config;
+pub mod network;
+pub mod plugins;
+pub mod protocol;
+pub mod world;
+
+mod builder;
+mod console;
+
+pub use builder::ServerBuilder;
+pub use config::Config;
+pub use network::NetworkServer;
+pub use plugins::Plugins;
+
+pub const VERSION: &str = std::env!("CARGO_PKG_VERSION");
@@ -1,27 +1,155 @@
 use std::borrow::Cow;
-use std::time::Duration;
 use std::io::Write;
+use std::path::PathBuf;
 
 use chrono::Utc;
 use env_logger::Env;
-use log::{info, warn};
-use mlua::Lua;
-use network::NetworkServer;
-use plugins::Plugins;
+use log::error;
+use log::info;
+use quectocraft::{config::{self, resolve_config, write_default_config, Config}, ServerBuilder, VERSION};
 
-use crate::config::{load_config, LoginMode};
+const USAGE: &str = "\
+Usage: quectocraft [options]
+       quectocraft [options] config validate
 
-mod config;
-mod plugins;
-mod protocol;
-mod network;
+Commands:
+    config validate        Check the config file and report any problems
 
-pub const VERSION: &str = std::env!("CARGO_PKG_VERSION");
+Options:
+    --config <path>        Config file to load, TOML or JSON (default:
+                           ./config.toml, or ./config.json if it exists)
+    --plugins-dir <path>   Directory to load plugins from
+    --port <port>          Port to listen on
+    --bind <addr>          Address to listen on
+    --log-level <level>    Log level or filter, e.g. debug or quectocraft=trace
+    -h, --help             Print this message
+    -V, --version          Print the version
+
+Every config field can also be set with a QC_<FIELD> environment variable,
+e.g. QC_PORT or QC_PLUGIN_DIR. Command-line options take precedence over
+environment variables, which take precedence over the config file.";
+
+#[derive(Default)]
+struct Args {
+    config: Option<PathBuf>,
+    log_level: Option<String>,
+    /// Words that aren't options, such as `config validate`.
+    command: Vec<String>,
+    /// Config fields to override, in the form accepted by `Config::set`.
+    overrides: Vec<(&'static str, String)>,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_owned(), Some(value.to_owned())),
+            _ => (arg, None),
+        };
+        match flag.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "-V" | "--version" => {
+                println!("quectocraft {}", VERSION);
+                std::process::exit(0);
+            }
+            "--config" | "--plugins-dir" | "--port" | "--bind" | "--log-level" => {
+                let value = match inline.or_else(|| args.next()) {
+                    Some(value) => value,
+                    None => return Err(format!("missing value for {}", flag)),
+                };
+                match flag.as_str() {
+                    "--config" => parsed.config = Some(value.into()),
+                    "--log-level" => parsed.log_level = Some(value),
+                    "--plugins-dir" => parsed.overrides.push(("plugin_dir", value)),
+                    "--port" => parsed.overrides.push(("port", value)),
+                    "--bind" => parsed.overrides.push(("addr", value)),
+                    _ => unreachable!(),
+                }
+            }
+            _ if !flag.starts_with('-') => parsed.command.push(flag),
+            _ => return Err(format!("unknown argument '{}'", flag)),
+        }
+    }
+    Ok(parsed)
+}
+
+impl Args {
+    /// The config file to use: the one given with `--config`, otherwise
+    /// `config.toml`, falling back to an existing `config.json`.
+    fn config_path(&self) -> PathBuf {
+        if let Some(path) = &self.config {
+            return path.clone()
+        }
+        let toml = PathBuf::from("./config.toml");
+        let json = PathBuf::from("./config.json");
+        if !toml.exists() && json.exists() { json } else { toml }
+    }
+}
+
+fn load_config(args: &Args) -> Result<Config, Box<dyn std::error::Error>> {
+    let path = args.config_path();
+    if !path.exists() && path.extension().is_some_and(|e| e == "toml") {
+        write_default_config(&path)
+            .map_err(|e| format!("couldn't create {}: {}", path.display(), e))?;
+        info!("Created default config file {}", path.display());
+    }
+    config::load_config(&path, &args.overrides)
+}
+
+/// Check the config file and print any problems, for `config validate`.
+fn validate_config(args: &Args) -> bool {
+    let path = args.config_path();
+    let file = match resolve_config(&path, &args.overrides) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("{}", e);
+            return false
+        }
+    };
+    let mut ok = true;
+    for key in &file.unknown_keys {
+        println!("{}: unknown key '{}'", path.display(), key);
+        ok = false;
+    }
+    if let Err(e) = file.config.validate() {
+        println!("{}: {}", path.display(), e);
+        ok = false;
+    }
+    if ok {
+        println!("{}: ok", path.display());
+    }
+    ok
+}
 
 fn main() {
-    env_logger::Builder::from_env(
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    match args.command.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => (),
+        ["config", "validate"] => std::process::exit(if validate_config(&args) { 0 } else { 1 }),
+        _ => {
+            eprintln!("unknown command '{}'\n\n{}", args.command.join(" "), USAGE);
+            std::process::exit(2);
+        }
+    }
+
+    let mut logger = env_logger::Builder::from_env(
         Env::default().default_filter_or("info")
-    ).format(|buf, record| {
+    );
+    if let Some(level) = &args.log_level {
+        logger.parse_filters(level);
+    }
+    logger.format(|buf, record| {
 
         let now = Utc::now().format("%Y-%m-%d %H:%M:%S");
         let mut target = Cow::Borrowed(record.target());
@@ -39,31 +167,16 @@ fn main() {
 
         writeln!(buf, "\x1b[90m[\x1b[37m{} {color}{}\x1b[37m {}\x1b[90m]\x1b[0m {}", now, record.level(), target, record.args())
     }).init();
-    
-    info!("Starting Quectocraft version {}", VERSION);
-
-    let config = load_config().expect("Failed to load config");
-    match config.login {
-        LoginMode::Offline => warn!("Running in offline mode!"),
-        LoginMode::Velocity => info!("Running in velocity mode"),
-    }
 
-    let lua = Lua::new();
-    let mut plugins = Plugins::new(&lua).expect("Error initializing lua environment");
-    std::fs::create_dir_all("plugins").expect("Couldn't create the plugins directory");
-    plugins.load_plugins();
-    
-    let mut server = NetworkServer::new(config, plugins);
-    let sleep_dur = Duration::from_millis(5);
-    let mut i = 0;
-    loop {
-        server.get_new_clients();
-        server.handle_connections();
-        if i % 1024 == 0 {
-            server.send_keep_alive();
-            i = 0;
+    let config = match load_config(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to load config: {}", e);
+            std::process::exit(1);
         }
-        i += 1;
-        std::thread::sleep(sleep_dur);
+    };
+    if let Err(e) = ServerBuilder::new().config(config).run() {
+        error!("{}", e);
+        std::process::exit(1);
     }
 }
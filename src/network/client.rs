@@ -19,14 +19,9 @@ impl NetworkClient {
     pub fn listen(mut stream: TcpStream, send: Sender<ServerBoundPacket>) {
         let mut state = NetworkState::Handshake;
         let dur = Duration::from_millis(5);
-        loop {
-            match PacketDecoder::decode(&mut stream) {
-                Ok(decoder) => {
-                    let packet = ServerBoundPacket::decode(&mut state, decoder);
-                    send.send(packet).unwrap();
-                }
-                Err(_) => break
-            }
+        while let Ok(decoder) = PacketDecoder::decode(&mut stream) {
+            let packet = ServerBoundPacket::decode(&mut state, decoder);
+            send.send(packet).unwrap();
             thread::sleep(dur)
         }
         let _ = stream.shutdown(Shutdown::Both);
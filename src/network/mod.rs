@@ -1,12 +1,6 @@
-use uuid::Uuid;
-
 mod client;
+mod player;
 mod server;
 
 pub use server::NetworkServer;
-
-#[derive(Debug)]
-pub struct Player {
-    pub name: String,
-    pub uuid: Uuid,
-}
+pub use player::{BlockEvent, JoinSettings, Player, PlayerInfo, PlayerRegistry, SharedPlayers, set_block};
@@ -0,0 +1,292 @@
+use std::{collections::{HashMap, HashSet}, net::{SocketAddr, TcpStream, Shutdown}, time::Duration, rc::Rc, cell::RefCell, io::Write};
+
+use uuid::Uuid;
+
+use crate::{config::GameMode, protocol::{clientbound::*, BlockFace, Position}, world::{World, WorldRegistry}};
+
+#[derive(Debug, Clone)]
+pub struct Player {
+    pub name: String,
+    pub uuid: Uuid,
+}
+
+/// What a player joins with. Starts out as configured, and can be changed
+/// for each player by `preLogin` handlers.
+#[derive(Debug, Clone)]
+pub struct JoinSettings {
+    /// Name of the world the player joins.
+    pub world: String,
+    pub position: (f64, f64, f64),
+    /// Yaw and pitch, in degrees.
+    pub rotation: (f32, f32),
+    pub gamemode: GameMode,
+    pub hardcore: bool,
+    /// How many chunks around them the player is sent, from 2 to 32.
+    pub view_distance: u8,
+    pub reduced_debug_info: bool,
+    pub respawn_screen: bool,
+}
+
+/// A player breaking, using or placing a block, passed to plugins before
+/// it happens. Handlers can set `cancelled` to undo it on the client.
+#[derive(Debug, Clone)]
+pub struct BlockEvent {
+    pub world: String,
+    pub position: (i32, i32, i32),
+    /// The side of the block that was clicked, or pointed at while digging.
+    pub face: Option<BlockFace>,
+    /// The block that is there now, or for placing, the block that will be
+    /// placed. `None` if the server doesn't know which block the held item
+    /// places, in which case nothing is placed.
+    pub state: Option<u32>,
+    pub cancelled: bool,
+}
+
+#[derive(Debug)]
+pub struct PlayerInfo {
+    pub player: Player,
+    pub addr: SocketAddr,
+    pub locale: Option<String>,
+    pub ping: Option<Duration>,
+    pub stream: TcpStream,
+    pub teleport_id: i32,
+    pub position: (f64, f64, f64),
+    pub rotation: (f32, f32),
+    pub gamemode: GameMode,
+    pub view_distance: u8,
+    /// Name of the world the player is in.
+    pub world: String,
+    /// The chunk the player's view is centered on.
+    pub chunk: (i32, i32),
+    /// Chunks that have been sent to the player and not unloaded.
+    pub loaded_chunks: HashSet<(i32, i32)>,
+    /// The selected hotbar slot, from 0 to 8.
+    pub held_slot: u8,
+    /// Item IDs in the player's inventory, by slot. Only creative mode
+    /// players tell the server what their items are.
+    pub items: HashMap<i16, i32>,
+}
+
+impl PlayerInfo {
+    pub fn send_packet(&self, packet: impl ClientBoundPacket) -> std::io::Result<()> {
+        (&self.stream).write_all(&encode_packet(packet))
+    }
+
+    pub fn send_message(&self, message: serde_json::Value) -> std::io::Result<()> {
+        self.send_packet(SystemChatMessage { message, overlay: false })
+    }
+
+    pub fn send_plugin_message(&self, channel: String, data: Vec<u8>) -> std::io::Result<()> {
+        self.send_packet(CPluginMessage { channel, data })
+    }
+
+    /// Send the disconnect packet and close the connection. The server
+    /// notices the closed connection on its next pass and runs the usual
+    /// leave handling.
+    pub fn disconnect(&self, reason: serde_json::Value) -> std::io::Result<()> {
+        let result = self.send_packet(Disconnect { reason });
+        let _ = self.stream.shutdown(Shutdown::Both);
+        result
+    }
+
+    /// Send the chunks around the player that they don't have yet, and
+    /// unload the ones that are now out of view. Only does anything once
+    /// they have moved into another chunk, unless `force` is set.
+    pub fn update_view(&mut self, world: &mut World, force: bool) -> std::io::Result<()> {
+        let (x, _, z) = self.position;
+        let center = ((x.floor() as i32).div_euclid(16), (z.floor() as i32).div_euclid(16));
+        if center == self.chunk && !force {
+            return Ok(())
+        }
+        self.chunk = center;
+        self.send_packet(SetCenterChunk { x: center.0, z: center.1 })?;
+
+        let radius = self.view_distance as i32;
+        let in_view = |(x, z): (i32, i32)| (x - center.0).abs() <= radius && (z - center.1).abs() <= radius;
+        let leaving: Vec<_> = self.loaded_chunks.iter().copied().filter(|c| !in_view(*c)).collect();
+        for (x, z) in leaving {
+            self.loaded_chunks.remove(&(x, z));
+            self.send_packet(UnloadChunk { x, z })?;
+        }
+
+        let mut entering: Vec<_> = (center.0 - radius..=center.0 + radius)
+            .flat_map(|x| (center.1 - radius..=center.1 + radius).map(move |z| (x, z)))
+            .filter(|c| !self.loaded_chunks.contains(c))
+            .collect();
+        // Nearest first, so the ground under the player arrives quickly.
+        entering.sort_by_key(|(x, z)| (x - center.0).abs().max((z - center.1).abs()));
+        let empty = world.empty_chunk();
+        for (x, z) in entering {
+            world.load_chunk(x, z);
+            let chunk = world.chunk(x, z).unwrap_or(&empty);
+            self.send_packet(ChunkData {
+                x,
+                z,
+                heightmap: chunk.heightmaps(),
+                chunk_data: world.chunk_data(chunk),
+                light: world.chunk_light(x, z),
+            })?;
+            self.loaded_chunks.insert((x, z));
+        }
+        Ok(())
+    }
+
+    /// Move the player to another world with the Respawn packet, and send
+    /// them its chunks. If they are already in that world they are only
+    /// teleported.
+    pub fn change_world(&mut self, name: &str, world: &mut World, position: (f64, f64, f64), rotation: (f32, f32)) -> std::io::Result<()> {
+        if self.world == name {
+            let (x, y, z) = position;
+            return self.teleport(x, y, z, rotation.0, rotation.1)
+        }
+        self.send_packet(Respawn {
+            dimension_type: world.dimension_type().to_owned(),
+            dimension_name: name.to_owned(),
+            seed_hash: 0,
+            gamemode: self.gamemode.id(),
+            prev_gamemode: 255,
+            is_debug: false,
+            is_flat: false,
+            copy_metadata: false,
+            death_location: None,
+        })?;
+        self.world = name.to_owned();
+        self.loaded_chunks.clear();
+        self.position = position;
+        self.update_view(world, true)?;
+        let (x, y, z) = position;
+        self.send_packet(SetDefaultSpawnPosition {
+            pos: Position { x: x.floor() as i32, y: y.floor() as i16, z: z.floor() as i32 }, angle: rotation.0
+        })?;
+        self.teleport(x, y, z, rotation.0, rotation.1)
+    }
+
+    /// The item in the main hand (`hand` 0) or off hand (`hand` 1), if it
+    /// is known.
+    pub fn held_item(&self, hand: i32) -> Option<i32> {
+        let slot = if hand == 0 { 36 + self.held_slot as i16 } else { 45 };
+        self.items.get(&slot).copied()
+    }
+
+    /// Send the block at a position as the server has it, undoing whatever
+    /// the client predicted there.
+    pub fn send_block(&self, world: &World, (x, y, z): (i32, i32, i32)) -> std::io::Result<()> {
+        self.send_packet(BlockUpdate {
+            pos: Position { x, y: y as i16, z },
+            state: world.block(x, y, z) as i32,
+        })
+    }
+
+    pub fn set_gamemode(&mut self, gamemode: GameMode) -> std::io::Result<()> {
+        self.gamemode = gamemode;
+        self.send_packet(GameEvent { event: GameEvent::CHANGE_GAMEMODE, value: gamemode.id() as f32 })
+    }
+
+    pub fn teleport(&mut self, x: f64, y: f64, z: f64, yaw: f32, pitch: f32) -> std::io::Result<()> {
+        self.teleport_id = self.teleport_id.wrapping_add(1);
+        self.position = (x, y, z);
+        self.rotation = (yaw, pitch);
+        self.send_packet(SyncPlayerPosition {
+            x, y, z, yaw, pitch,
+            flags: 0,
+            teleport_id: self.teleport_id,
+            dismount: false,
+        })
+    }
+}
+
+/// Set a block in a world, and send it to the players who can see it.
+pub fn set_block(players: &PlayerRegistry, worlds: &mut WorldRegistry, name: &str, (x, y, z): (i32, i32, i32), state: u32) -> Result<(), String> {
+    let world = worlds.get_mut(name).ok_or_else(|| format!("unknown world '{}'", name))?;
+    if !world.set_block(x, y, z, state) {
+        return Err(format!("y={} is outside the world", y))
+    }
+    players.send_block_change(name, world, (x, y, z));
+    Ok(())
+}
+
+pub type SharedPlayers = Rc<RefCell<PlayerRegistry>>;
+
+/// Online players, indexed by UUID and by lowercase name.
+#[derive(Debug, Default)]
+pub struct PlayerRegistry {
+    players: HashMap<Uuid, PlayerInfo>,
+    names: HashMap<String, Uuid>,
+}
+
+impl PlayerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn shared() -> SharedPlayers {
+        Rc::new(RefCell::new(Self::new()))
+    }
+
+    pub fn insert(&mut self, info: PlayerInfo) {
+        self.names.insert(info.player.name.to_lowercase(), info.player.uuid);
+        self.players.insert(info.player.uuid, info);
+    }
+
+    pub fn remove(&mut self, uuid: &Uuid) -> Option<PlayerInfo> {
+        let info = self.players.remove(uuid)?;
+        self.names.remove(&info.player.name.to_lowercase());
+        Some(info)
+    }
+
+    pub fn get(&self, uuid: &Uuid) -> Option<&PlayerInfo> {
+        self.players.get(uuid)
+    }
+
+    pub fn get_mut(&mut self, uuid: &Uuid) -> Option<&mut PlayerInfo> {
+        self.players.get_mut(uuid)
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<&PlayerInfo> {
+        self.players.get(self.names.get(&name.to_lowercase())?)
+    }
+
+    /// Look up a player by either their UUID or their (case-insensitive) name.
+    pub fn find(&self, key: &str) -> Option<&PlayerInfo> {
+        match Uuid::parse_str(key) {
+            Ok(uuid) => self.get(&uuid),
+            Err(_) => self.get_by_name(key),
+        }
+    }
+
+    pub fn find_mut(&mut self, key: &str) -> Option<&mut PlayerInfo> {
+        let uuid = match Uuid::parse_str(key) {
+            Ok(uuid) => uuid,
+            Err(_) => *self.names.get(&key.to_lowercase())?,
+        };
+        self.players.get_mut(&uuid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PlayerInfo> {
+        self.players.values()
+    }
+
+    /// Send a changed block, and the new light of its chunk, to every player
+    /// in the world who has the chunk loaded.
+    pub fn send_block_change(&self, name: &str, world: &World, (x, y, z): (i32, i32, i32)) {
+        let (cx, cz) = (x.div_euclid(16), z.div_euclid(16));
+        let viewers: Vec<_> = self.players.values()
+            .filter(|p| p.world == name && p.loaded_chunks.contains(&(cx, cz)))
+            .collect();
+        if viewers.is_empty() {
+            return
+        }
+        let light = world.chunk_light(cx, cz);
+        for player in viewers {
+            let _ = player.send_block(world, (x, y, z));
+            let _ = player.send_packet(UpdateLight { x: cx, z: cz, light: light.clone() });
+        }
+    }
+
+    /// Send a packet to every online player, returning how many received it.
+    pub fn broadcast(&self, packet: impl ClientBoundPacket + Clone) -> usize {
+        self.players.values()
+            .filter(|p| p.send_packet(packet.clone()).is_ok())
+            .count()
+    }
+}
@@ -1,40 +1,150 @@
-use std::{net::{TcpListener, SocketAddr}, thread, sync::mpsc::{Receiver, Sender, channel}, collections::HashSet};
+use std::{net::{TcpListener, SocketAddr}, thread, sync::mpsc::{Receiver, Sender, channel}, collections::{HashMap, HashSet}, time::{Instant, Duration}};
 
 use hmac::{Hmac, Mac};
 use log::{info, warn, debug, trace};
 use serde_json::json;
 use sha2::Sha256;
 
-use crate::{protocol::{data::PacketEncoder, serverbound::*, clientbound::*, command::Commands, Position}, config::{Config, LoginMode}};
-use crate::plugins::{Plugins, Response};
+use crate::{protocol::{data::PacketEncoder, serverbound::*, clientbound::*, command::Commands, registry::RegistryCodec, Position}, config::{Config, GameMode, LoginMode}};
+use crate::plugins::Plugins;
+use crate::world::{chunk::AIR, SharedWorlds, WorldRegistry};
 use crate::VERSION;
 
-use super::{client::NetworkClient, Player};
+use super::{client::NetworkClient, set_block, BlockEvent, JoinSettings, Player, PlayerInfo, SharedPlayers};
 
 pub struct NetworkServer<'lua> {
     plugins: Plugins<'lua>,
+    players: SharedPlayers,
     commands: Commands,
     new_clients: Receiver<NetworkClient>,
     clients: Vec<NetworkClient>,
     config: Config,
+    worlds: SharedWorlds,
+    /// The encoded registry codec sent on login.
+    registry_codec: Vec<u8>,
+    start_time: Instant,
+    reload_requested: bool,
+    stop_requested: bool,
 }
 
 impl <'lua> NetworkServer<'lua> {
-    pub fn new(config: Config, mut plugins: Plugins<'lua>) -> Self {
+    pub fn new(config: Config, mut plugins: Plugins<'lua>, worlds: WorldRegistry, registry_codec: &RegistryCodec) -> Self {
         let (send, recv) = channel();
+        *plugins.worlds().borrow_mut() = worlds;
         info!("Initializing plugins");
         plugins.init();
-        let mut commands = Commands::new();
-        commands.create_simple_cmd("qc");
-        let commands = plugins.register_commands(commands).unwrap();
+        let commands = Self::build_commands(&mut plugins);
         thread::spawn(move || Self::listen(&SocketAddr::new(config.addr, config.port), send));
         Self {
             config,
+            worlds: plugins.worlds(),
+            registry_codec: registry_codec.encode(),
+            players: plugins.players(),
             plugins,
             commands,
             new_clients: recv,
             clients: Vec::new(),
+            start_time: Instant::now(),
+            reload_requested: false,
+            stop_requested: false,
+        }
+    }
+
+    fn build_commands(plugins: &mut Plugins) -> Commands {
+        let mut commands = Commands::new();
+        commands.create_simple_cmd("qc");
+        plugins.register_commands(commands)
+    }
+
+    /// Reload every plugin, then bring online players up to date: resend
+    /// the command tree and repeat `playerJoin` for each of them.
+    pub fn reload_plugins(&mut self) {
+        self.reload_requested = false;
+        info!("Reloading plugins");
+        self.plugins.reload();
+        self.commands = Self::build_commands(&mut self.plugins);
+        for client in self.clients.iter_mut() {
+            if client.player.is_some() && client.send_packet(self.commands.clone()).is_err() {
+                client.close();
+            }
+        }
+        for client in &self.clients {
+            if let (Some(pl), false) = (&client.player, client.closed) {
+                self.plugins.player_join(pl, true);
+            }
+        }
+        info!("Reloaded plugins");
+    }
+
+    pub fn check_plugin_changes(&mut self) {
+        if self.config.watch_plugins && self.plugins.changed_on_disk() {
+            info!("Plugin files changed");
+            self.reload_plugins();
+        }
+    }
+
+    pub fn handle_console(&mut self, line: &str) {
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("reload") => self.reload_plugins(),
+            Some("plugins") => {
+                let list: Vec<String> = self.plugins.loaded().iter()
+                    .map(|pl| {
+                        let p = pl.plugin();
+                        let mut desc = format!("{} ({}) {}", p.name(), p.id(), p.version());
+                        if !p.capabilities().is_empty() {
+                            desc += &format!(" [{}]", p.capabilities().join(", "));
+                        }
+                        if pl.disabled() {
+                            desc += " (disabled)";
+                        }
+                        desc
+                    })
+                    .collect();
+                info!("Plugins ({}): {}", list.len(), list.join(", "));
+            }
+            Some("stop") => self.stop_requested = true,
+            Some("help") => info!("Console commands: help, plugins, reload, stop"),
+            Some(cmd) => warn!("Unknown console command '{}'. Type 'help' for a list of commands", cmd),
+            None => (),
+        }
+    }
+
+    pub fn stop_requested(&self) -> bool {
+        self.stop_requested
+    }
+
+    /// Disconnect every player and save plugin data before the server exits.
+    pub fn shutdown(&mut self) {
+        info!("Stopping server");
+        let reason = json!({ "translate": "multiplayer.disconnect.server_shutdown" });
+        for client in self.clients.iter_mut() {
+            if let Some(pl) = &client.player {
+                if let Some(info) = self.players.borrow().get(&pl.uuid) {
+                    let _ = info.disconnect(reason.clone());
+                }
+                self.plugins.player_leave(pl);
+                self.players.borrow_mut().remove(&pl.uuid);
+            }
+            client.close();
         }
+        self.clients.clear();
+        self.plugins.save();
+    }
+
+    pub fn tick(&self) {
+        self.plugins.tick();
+    }
+
+    /// Write plugin data to disk.
+    pub fn save(&self) {
+        self.plugins.save();
+    }
+
+    fn is_operator(&self, player: &Player) -> bool {
+        let uuid = player.uuid.to_string();
+        self.config.operators.iter()
+            .any(|op| op.eq_ignore_ascii_case(&player.name) || op.eq_ignore_ascii_case(&uuid))
     }
 
     fn listen(addr: &SocketAddr, send_clients: Sender<NetworkClient>) {
@@ -54,7 +164,10 @@ impl <'lua> NetworkServer<'lua> {
                 serverbound: recv,
                 player: None,
             };
-            send_clients.send(client).unwrap();
+            if send_clients.send(client).is_err() {
+                // the server has stopped
+                break
+            }
         }
     }
 
@@ -66,12 +179,15 @@ impl <'lua> NetworkServer<'lua> {
 
     pub fn send_keep_alive(&mut self) {
         let mut closed = Vec::new();
+        let data = self.start_time.elapsed().as_millis() as i64;
         for client in self.clients.iter_mut() {
             if client.player.is_some() {
-                let result = client.send_packet(KeepAlive { data: 0 });
+                let result = client.send_packet(KeepAlive { data });
                 if result.is_err() {
                     client.close();
-                    self.plugins.player_leave(client.player.as_ref().unwrap());
+                    let pl = client.player.as_ref().unwrap();
+                    self.plugins.player_leave(pl);
+                    self.players.borrow_mut().remove(&pl.uuid);
                     closed.push(client.id);
                 }
             }
@@ -87,9 +203,8 @@ impl <'lua> NetworkServer<'lua> {
             };
             let mut alive = true;
             while let Some(packet) = client.recv_packet(&mut alive) {
-                let result = self.handle_packet(client, packet);
-                if result.is_err() {
-                    warn!("error: {}", result.unwrap_err());
+                if let Err(e) = self.handle_packet(client, packet) {
+                    warn!("error: {}", e);
                     alive = false;
                     break
                 }
@@ -98,56 +213,15 @@ impl <'lua> NetworkServer<'lua> {
                 closed.insert(client.id);
                 if let Some(pl) = &client.player {
                     self.plugins.player_leave(pl);
+                    self.players.borrow_mut().remove(&pl.uuid);
                 }
                 client.close();
             }
         }
-        for response in self.plugins.get_responses() {
-            let _ = self.handle_plugin_response(response);
-        }
         self.clients.retain(|x| !closed.contains(&x.id) && !x.closed);
-    }
-
-    fn handle_plugin_response(&mut self, response: Response) -> std::io::Result<()> {
-        match response {
-            Response::Message { player, message } => {
-                for client in self.clients.iter_mut() {
-                    if let Some(p) = &client.player {
-                        if p.name == player || p.uuid.to_string() == player {
-                            client.send_packet(SystemChatMessage { message, overlay: false })?;
-                            break
-                        }
-                    }
-                }
-            },
-            Response::Broadcast { message } => {
-                for client in self.clients.iter_mut() {
-                    if client.player.is_some() {
-                        client.send_packet(SystemChatMessage { message: message.clone(), overlay: false })?;
-                    }
-                }
-            },
-            Response::Disconnect { player, reason } => {
-                for client in self.clients.iter_mut() {
-                    if let Some(pl) = &client.player {
-                        if pl.name == player || pl.uuid.to_string() == player {
-                            client.send_packet(Disconnect { reason: reason.clone() })?;
-                        }
-                    }
-                }
-            },
-            Response::PluginMessage { player, channel, data } => {
-                for client in self.clients.iter_mut() {
-                    if let Some(pl) = &client.player {
-                        if pl.name == player || pl.uuid.to_string() == player {
-                            client.send_packet(CPluginMessage { channel: channel.clone(), data: data.clone() })?;
-                        }
-                    }
-                }
-            }
-,
+        if self.reload_requested {
+            self.reload_plugins();
         }
-        Ok(())
     }
 
     fn handle_packet(&mut self, client: &mut NetworkClient, packet: ServerBoundPacket) -> Result<(), Box<dyn std::error::Error>> {
@@ -181,10 +255,7 @@ impl <'lua> NetworkServer<'lua> {
                 let mut parts = msg.message.splitn(2, ' ');
                 if let Some(cmd) = parts.next() {
                     if cmd == "qc" {
-                        client.send_packet(SystemChatMessage { message: json!({
-                            "text": format!("QuectoCraft version {}", VERSION),
-                            "color": "green"
-                        }), overlay: false })?;
+                        self.qc_command(client, parts.next().unwrap_or_default())?;
                     } else {
                         let args = parts.next().unwrap_or_default();
                         self.plugins.command(client.player.as_ref().unwrap(), cmd, args);
@@ -194,10 +265,198 @@ impl <'lua> NetworkServer<'lua> {
             ServerBoundPacket::PluginMessage(SPluginMessage { channel, data }) => {
                 self.plugins.plugin_message(client.player.as_ref().unwrap(), &channel, &data);
             }
+            ServerBoundPacket::ClientInformation(info) => {
+                let uuid = client.player.as_ref().unwrap().uuid;
+                if let Some(pl) = self.players.borrow_mut().get_mut(&uuid) {
+                    pl.locale = Some(info.locale);
+                }
+            }
+            ServerBoundPacket::PlayerMovement(movement) => {
+                let uuid = client.player.as_ref().unwrap().uuid;
+                if let Some(pl) = self.players.borrow_mut().get_mut(&uuid) {
+                    if let Some(position) = movement.position {
+                        pl.position = position;
+                    }
+                    if let Some(rotation) = movement.rotation {
+                        pl.rotation = rotation;
+                    }
+                }
+                self.update_view(client, false)?;
+            }
+            ServerBoundPacket::PlayerAction(action) => self.player_action(client, action)?,
+            ServerBoundPacket::UseItemOn(use_item) => self.use_item_on(client, use_item)?,
+            ServerBoundPacket::SetHeldItem(slot) => {
+                let uuid = client.player.as_ref().unwrap().uuid;
+                if let Some(pl) = self.players.borrow_mut().get_mut(&uuid) {
+                    pl.held_slot = slot.clamp(0, 8) as u8;
+                }
+            }
+            ServerBoundPacket::SetCreativeModeSlot(SetCreativeModeSlot { slot, item }) => {
+                let uuid = client.player.as_ref().unwrap().uuid;
+                if let Some(pl) = self.players.borrow_mut().get_mut(&uuid) {
+                    match item {
+                        Some(item) => pl.items.insert(slot, item),
+                        None => pl.items.remove(&slot),
+                    };
+                }
+            }
+            ServerBoundPacket::KeepAlive(data) => {
+                let uuid = client.player.as_ref().unwrap().uuid;
+                let now = self.start_time.elapsed().as_millis() as i64;
+                if let Some(pl) = self.players.borrow_mut().get_mut(&uuid) {
+                    pl.ping = Some(Duration::from_millis(now.saturating_sub(data).max(0) as u64));
+                }
+            }
         }
         Ok(())
     }
 
+    /// Break a block once a player has finished digging it (or started, in
+    /// creative mode or for blocks that break instantly), unless a
+    /// `blockBreak` handler cancels it.
+    fn player_action(&mut self, client: &mut NetworkClient, action: PlayerAction) -> std::io::Result<()> {
+        if !(PlayerAction::START_DIGGING..=PlayerAction::FINISH_DIGGING).contains(&action.status) {
+            return Ok(())
+        }
+        let player = client.player.clone().unwrap();
+        let Some((world, gamemode)) = self.players.borrow().get(&player.uuid).map(|p| (p.world.clone(), p.gamemode)) else {
+            return Ok(())
+        };
+        let Position { x, y, z } = action.location;
+        let position = (x, y as i32, z);
+        let state = self.worlds.borrow().get(&world).map(|w| w.block(x, y as i32, z));
+        let instant = state.is_some_and(|state| self.worlds.borrow().blocks().breaks_instantly(state));
+        let breaks = breaks_block(action.status, gamemode, instant);
+        let mut changed = Vec::new();
+        if breaks {
+            let mut event = BlockEvent { world: world.clone(), position, face: action.face, state, cancelled: false };
+            self.plugins.block_break(&player, &mut event);
+            if !event.cancelled {
+                self.set_block(&world, position, AIR);
+            }
+            changed.push(position);
+        }
+        self.acknowledge_block_changes(&player, &world, &changed, action.sequence)
+    }
+
+    /// Run the `blockInteract` handlers for a block a player right clicked,
+    /// then place the block they are holding against it. Only the items of
+    /// creative mode players are known, so only they can place blocks.
+    fn use_item_on(&mut self, client: &mut NetworkClient, use_item: UseItemOn) -> std::io::Result<()> {
+        let player = client.player.clone().unwrap();
+        let Some((world, gamemode, item)) = self.players.borrow().get(&player.uuid)
+            .map(|p| (p.world.clone(), p.gamemode, p.held_item(use_item.hand))) else {
+            return Ok(())
+        };
+        let Position { x, y, z } = use_item.location;
+        let clicked = (x, y as i32, z);
+        let (dx, dy, dz) = use_item.face.offset();
+        let target = (x + dx, y as i32 + dy, z + dz);
+
+        let state = self.worlds.borrow().get(&world).map(|w| w.block(x, y as i32, z));
+        let mut event = BlockEvent { world: world.clone(), position: clicked, face: Some(use_item.face), state, cancelled: false };
+        self.plugins.block_interact(&player, &mut event);
+
+        let can_place = gamemode == GameMode::Creative;
+        if let (false, true, Some(item)) = (event.cancelled, can_place, item) {
+            let (state, free) = {
+                let worlds = self.worlds.borrow();
+                let free = worlds.get(&world).is_some_and(|w| w.block(target.0, target.1, target.2) == AIR);
+                (worlds.blocks().item_block(item), free)
+            };
+            if free {
+                let mut event = BlockEvent { world: world.clone(), position: target, face: Some(use_item.face), state, cancelled: false };
+                self.plugins.block_place(&player, &mut event);
+                if let (false, Some(state)) = (event.cancelled, event.state) {
+                    self.set_block(&world, target, state);
+                }
+            }
+        }
+        self.acknowledge_block_changes(&player, &world, &[clicked, target], use_item.sequence)
+    }
+
+    fn set_block(&self, world: &str, position: (i32, i32, i32), state: u32) {
+        let mut worlds = self.worlds.borrow_mut();
+        if let Err(e) = set_block(&self.players.borrow(), &mut worlds, world, position, state) {
+            debug!("Couldn't set block: {}", e);
+        }
+    }
+
+    /// Send a player the blocks they may have changed on their side, as the
+    /// server has them, then acknowledge the change so the client uses them
+    /// instead of its own prediction.
+    fn acknowledge_block_changes(&self, player: &Player, world: &str, positions: &[(i32, i32, i32)], sequence: i32) -> std::io::Result<()> {
+        let players = self.players.borrow();
+        let Some(pl) = players.get(&player.uuid) else {
+            return Ok(())
+        };
+        if let Some(world) = self.worlds.borrow().get(world) {
+            for position in positions {
+                pl.send_block(world, *position)?;
+            }
+        }
+        pl.send_packet(AcknowledgeBlockChange { sequence })
+    }
+
+    fn qc_command(&mut self, client: &mut NetworkClient, args: &str) -> std::io::Result<()> {
+        let message = match args.trim() {
+            "" => json!({
+                "text": format!("QuectoCraft version {}", VERSION),
+                "color": "green"
+            }),
+            "plugins" => self.plugin_list(),
+            "reload" if self.is_operator(client.player.as_ref().unwrap()) => {
+                // Reloading from inside the packet loop would resend packets to
+                // the client currently being handled, so defer it until the end
+                // of this pass.
+                self.reload_requested = true;
+                json!({ "text": "Reloading plugins", "color": "green" })
+            }
+            "reload" => json!({
+                "text": "You don't have permission to do that",
+                "color": "red"
+            }),
+            _ => json!({
+                "text": "Usage: /qc [plugins|reload]",
+                "color": "red"
+            }),
+        };
+        client.send_packet(SystemChatMessage { message, overlay: false })
+    }
+
+    fn plugin_list(&self) -> serde_json::Value {
+        let mut extra = Vec::new();
+        for (i, pl) in self.plugins.loaded().iter().enumerate() {
+            if i > 0 {
+                extra.push(json!({ "text": ", ", "color": "white" }));
+            }
+            let p = pl.plugin();
+            let mut hover = format!("{} {}", p.id(), p.version());
+            if let Some(desc) = p.description() {
+                hover += &format!("\n{}", desc);
+            }
+            if !p.authors().is_empty() {
+                hover += &format!("\nBy {}", p.authors().join(", "));
+            }
+            if !p.capabilities().is_empty() {
+                hover += &format!("\nCapabilities: {}", p.capabilities().join(", "));
+            }
+            if pl.disabled() {
+                hover += "\nDisabled after repeated errors";
+            }
+            extra.push(json!({
+                "text": p.name(),
+                "color": if pl.disabled() { "red" } else { "green" },
+                "hoverEvent": { "action": "show_text", "contents": hover },
+            }));
+        }
+        json!({
+            "text": format!("Plugins ({}): ", self.plugins.loaded().len()),
+            "color": "white",
+            "extra": extra,
+        })
+    }
+
     fn start_login(&mut self, client: &mut NetworkClient, login_start: LoginStart) -> Result<(), Box<dyn std::error::Error>> {
         if self.clients.iter().filter_map(|x| x.player.as_ref()).any(|x| x.uuid == login_start.uuid) {
             client.send_packet(LoginDisconnect { reason: json!({
@@ -268,6 +527,19 @@ impl <'lua> NetworkServer<'lua> {
     //
     // Handle the end of "login" and beginning of "play"
     //
+    fn update_view(&mut self, client: &mut NetworkClient, force: bool) -> std::io::Result<()> {
+        let uuid = client.player.as_ref().unwrap().uuid;
+        let mut players = self.players.borrow_mut();
+        let Some(pl) = players.get_mut(&uuid) else {
+            return Ok(())
+        };
+        let mut worlds = self.worlds.borrow_mut();
+        match worlds.get_mut(&pl.world) {
+            Some(world) => pl.update_view(world, force),
+            None => Ok(()),
+        }
+    }
+
     fn login(&mut self, client: &mut NetworkClient) -> std::io::Result<()> {
         if !client.verified {
             client.send_packet(Disconnect { reason: json!({
@@ -283,25 +555,71 @@ impl <'lua> NetworkServer<'lua> {
             uuid: client.player.as_ref().unwrap().uuid,
         })?;
 
-        self.plugins.player_join(client.player.as_ref().unwrap());
+        let worlds = self.worlds.borrow();
+        let Some(world_name) = worlds.default_world().map(str::to_owned) else {
+            return Err(std::io::Error::other("no world to join"))
+        };
+        let (sx, sy, sz) = worlds.get(&world_name).unwrap().spawn();
+        drop(worlds);
+        let mut settings = JoinSettings {
+            world: world_name,
+            position: match self.config.spawn {
+                Some([x, y, z]) => (x, y, z),
+                None => (sx as f64 + 0.5, sy as f64, sz as f64 + 0.5),
+            },
+            rotation: (self.config.spawn_yaw, self.config.spawn_pitch),
+            gamemode: self.config.gamemode,
+            hardcore: self.config.hardcore,
+            view_distance: self.config.view_distance,
+            reduced_debug_info: self.config.reduced_debug_info,
+            respawn_screen: self.config.respawn_screen,
+        };
+        self.plugins.pre_login(client.player.as_ref().unwrap(), &mut settings);
+        let worlds = self.worlds.borrow();
+        if worlds.get(&settings.world).is_none() {
+            warn!("Unknown world '{}' for {}, using the default world", settings.world, client.player.as_ref().unwrap().name);
+            settings.world = worlds.default_world().unwrap().to_owned();
+        }
+        let dimension_type = worlds.get(&settings.world).unwrap().dimension_type().to_owned();
+        let dimensions = worlds.names().map(str::to_owned).collect();
+        drop(worlds);
+        let (x, y, z) = settings.position;
+        let (yaw, pitch) = settings.rotation;
+
+        self.players.borrow_mut().insert(PlayerInfo {
+            player: client.player.clone().unwrap(),
+            addr: client.stream.peer_addr()?,
+            locale: None,
+            ping: None,
+            stream: client.stream.try_clone()?,
+            teleport_id: 0,
+            position: settings.position,
+            rotation: settings.rotation,
+            gamemode: settings.gamemode,
+            view_distance: settings.view_distance,
+            world: settings.world.clone(),
+            chunk: ((x.floor() as i32).div_euclid(16), (z.floor() as i32).div_euclid(16)),
+            loaded_chunks: HashSet::new(),
+            held_slot: 0,
+            items: HashMap::new(),
+        });
 
         client.send_packet(LoginPlay {
             eid: client.id,
-            is_hardcore: false,
-            gamemode: 3,
-            prev_gamemode: 3,
-            dimensions: vec![
-                "qc:world".to_owned(), 
-            ],
-            registry_codec: include_bytes!("../resources/registry_codec.nbt").to_vec(),
-            dimension_type: "minecraft:the_end".to_owned(),
-            dimension_name: "qc:world".to_owned(),
+            is_hardcore: settings.hardcore,
+            gamemode: settings.gamemode.id(),
+            // no previous game mode
+            prev_gamemode: 255,
+            dimensions,
+            registry_codec: self.registry_codec.clone(),
+            dimension_type,
+            dimension_name: settings.world.clone(),
             seed_hash: 0,
             max_players: 0,
-            view_distance: 8,
-            sim_distance: 8,
-            reduced_debug_info: false,
-            respawn_screen: false,
+            view_distance: settings.view_distance as i32,
+            sim_distance: settings.view_distance as i32,
+            reduced_debug_info: settings.reduced_debug_info,
+            respawn_screen: settings.respawn_screen,
             is_debug: false,
             is_flat: false,
             death_location: None,
@@ -318,50 +636,81 @@ impl <'lua> NetworkServer<'lua> {
 
         client.send_packet(self.commands.clone())?;
 
-        // Send 3x3 square of empty chunks.
-        let mut chunk_data: Vec<u8> = Vec::new();
-        for _ in 0..(384 / 16) {
-            // number of non-air blocks
-            chunk_data.write_short(0);
-            // block states
-            chunk_data.write_ubyte(0);
-            chunk_data.write_varint(0);
-            chunk_data.write_varint(0);
-            // biomes
-            chunk_data.write_ubyte(0);
-            chunk_data.write_varint(0);
-            chunk_data.write_varint(0);
-        }
-        let hmdata = vec![0i64; 37];
-        let mut heightmap = nbt::Blob::new();
-        heightmap.insert("MOTION_BLOCKING", hmdata).unwrap();
-        for x in -1..=1 {
-            for z in -1..=1 {
-                client.send_packet(ChunkData {
-                    x,
-                    z,
-                    heightmap: heightmap.clone(),
-                    chunk_data: chunk_data.clone(),
-                })?;
-            }
-        }
+        self.update_view(client, true)?;
 
         client.send_packet(SetDefaultSpawnPosition {
-            pos: Position { x: 0, y: 0, z: 0 }, angle: 0.0
+            pos: Position { x: x.floor() as i32, y: y.floor() as i16, z: z.floor() as i32 }, angle: yaw
         })?;
 
         client.send_packet(SyncPlayerPosition {
-            x: 0.0,
-            y: 64.0,
-            z: 0.0,
-            yaw: 0.0,
-            pitch: 0.0,
+            x,
+            y,
+            z,
+            yaw,
+            pitch,
             flags: 0,
             teleport_id: 0,
             dismount: false
         })?;
 
+        // Plugin actions run immediately, so only announce the player once
+        // the client is fully in the play state.
+        self.plugins.player_join(client.player.as_ref().unwrap(), false);
+
         Ok(())
     }
 
 }
+
+/// Whether a player action with `status` breaks a block. Creative players
+/// break blocks as soon as they start digging; survival players when they
+/// finish, or when they start on a block that breaks instantly, since the
+/// client doesn't report finishing those.
+fn breaks_block(status: i32, gamemode: GameMode, instant: bool) -> bool {
+    match (status, gamemode) {
+        (PlayerAction::START_DIGGING, GameMode::Creative) => true,
+        (PlayerAction::START_DIGGING, GameMode::Survival) => instant,
+        (PlayerAction::FINISH_DIGGING, GameMode::Survival) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::blocks::BlockRegistry;
+
+    #[test]
+    fn survival_digging() {
+        assert!(!breaks_block(PlayerAction::START_DIGGING, GameMode::Survival, false));
+        assert!(breaks_block(PlayerAction::START_DIGGING, GameMode::Survival, true));
+        assert!(!breaks_block(PlayerAction::CANCEL_DIGGING, GameMode::Survival, false));
+        assert!(breaks_block(PlayerAction::FINISH_DIGGING, GameMode::Survival, false));
+    }
+
+    #[test]
+    fn other_gamemodes() {
+        assert!(breaks_block(PlayerAction::START_DIGGING, GameMode::Creative, false));
+        assert!(!breaks_block(PlayerAction::FINISH_DIGGING, GameMode::Creative, false));
+        for status in [PlayerAction::START_DIGGING, PlayerAction::FINISH_DIGGING] {
+            assert!(!breaks_block(status, GameMode::Adventure, true));
+            assert!(!breaks_block(status, GameMode::Spectator, true));
+        }
+    }
+
+    #[test]
+    fn instant_blocks() {
+        let dir = std::env::temp_dir().join(format!("qc-instant-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let report = dir.join("blocks.json");
+        let names = ["stone", "torch", "wall_torch", "red_tulip", "potted_poppy", "mangrove_roots"];
+        let blocks: Vec<_> = names.iter().enumerate()
+            .map(|(id, name)| format!(r#""minecraft:{}": {{"states": [{{"id": {}, "default": true}}]}}"#, name, id))
+            .collect();
+        std::fs::write(&report, format!("{{{}}}", blocks.join(","))).unwrap();
+        let registry = BlockRegistry::load_report(&report).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let instant: Vec<_> = names.iter().filter(|name| registry.breaks_instantly(registry.state(name).unwrap())).collect();
+        assert_eq!(instant, [&"torch", &"wall_torch", &"red_tulip", &"potted_poppy"]);
+    }
+}
@@ -1,48 +1,267 @@
 server = { players = {} }
-_qc = { responses = {} }
+_qc = {}
 
-local function to_chat(message, default)
-    if message == nil then
-        if default ~= nil then
-            return default
-        else
-            error("message must be a string or table")
+-- Plugins can't reach into the string metatable to change string methods
+-- for everyone else.
+getmetatable("").__metatable = false
+
+local safe_globals = {
+    "_VERSION", "assert", "error", "getmetatable", "ipairs", "next", "pairs", "pcall",
+    "print", "rawequal", "rawget", "rawlen", "select", "tonumber", "tostring",
+    "type", "xpcall",
+}
+local safe_libs = { "coroutine", "math", "string", "table", "utf8" }
+local safe_os = { "clock", "date", "difftime", "time" }
+
+local function copy(t)
+    local c = {}
+    for k, v in pairs(t) do
+        c[k] = v
+    end
+    return c
+end
+
+-- Custom events and services registered by plugins. Each entry records the
+-- `owner` table of the plugin that registered it.
+local listeners = {}
+local services = {}
+
+function _qc.reset()
+    listeners = {}
+    services = {}
+end
+
+-- The functions in the `server` table that need to know which plugin is
+-- calling them
+local function plugin_api(owner, id)
+    local api = {}
+
+    -- Arguments are ignored: a plugin can only open its own storage. `id`
+    -- is the ID the plugin was loaded with, until its plugin table has been
+    -- read and it is known by its own ID.
+    function api.storage()
+        return _qc.openStorage(owner.id or id)
+    end
+
+    function api.on(name, listener)
+        if type(listener) ~= "function" then
+            error("listener must be a function", 2)
         end
-    elseif type(message) == "table" then
-        return message
-    elseif type(message) == "string" then
-        return { text = message }
-    elseif default == nil then
-        error("message must be a string or table")
-    else
-        error("message must be a string, table, or nil for the default message")
+        name = tostring(name)
+        listeners[name] = listeners[name] or {}
+        table.insert(listeners[name], { owner = owner, fn = listener })
     end
+
+    function api.emit(name, data)
+        local called = 0
+        -- listeners added while the event is running only see later events
+        local current = { table.unpack(listeners[tostring(name)] or {}) }
+        for _, listener in ipairs(current) do
+            if listener.owner.id ~= nil and _qc.dispatch(listener.owner.id, listener.fn, data) then
+                called = called + 1
+            end
+        end
+        return called
+    end
+
+    function api.provide(name, service)
+        name = tostring(name)
+        local existing = services[name]
+        if existing ~= nil and existing.owner ~= owner and existing.owner.id ~= nil then
+            error("service '" .. name .. "' is already provided by " .. existing.owner.id, 2)
+        end
+        services[name] = { owner = owner, service = service }
+    end
+
+    function api.service(name)
+        local entry = services[tostring(name)]
+        if entry ~= nil and entry.owner.id ~= nil then
+            return entry.service
+        end
+        return nil
+    end
+
+    return api
 end
 
-function server.sendMessage(player, message)
-    if type(player) ~= "string" then
-        error("player must be a string")
+-- A view of a table that can be read and iterated but not changed. Tables
+-- inside it are wrapped in the same way.
+local function read_only(t, message)
+    local views = setmetatable({}, { __mode = "k" })
+    return setmetatable({}, {
+        __index = function(_, key)
+            local value = t[key]
+            if type(value) == "table" then
+                views[value] = views[value] or read_only(value, message)
+                return views[value]
+            end
+            return value
+        end,
+        __newindex = function()
+            error(message, 2)
+        end,
+        __pairs = function(view)
+            return function(_, key)
+                local next_key = next(t, key)
+                return next_key, view[next_key]
+            end, view, nil
+        end,
+        __len = function()
+            return #t
+        end,
+        __metatable = false,
+    })
+end
+
+-- The `server` table as seen by a plugin. Tables in it, such as `players`,
+-- are shared between plugins, so they are only handed out as read-only views.
+local function server_proxy(owner, id)
+    local api = plugin_api(owner, id)
+    local shared = read_only(server, "the server table is read-only")
+    return setmetatable({}, {
+        __index = function(_, key)
+            local value = api[key]
+            if value ~= nil then
+                return value
+            end
+            return shared[key]
+        end,
+        __newindex = function()
+            error("the server table is read-only", 2)
+        end,
+        __pairs = function()
+            return pairs(shared)
+        end,
+        __metatable = false,
+    })
+end
+
+-- Extra access a plugin can request through its `capabilities` field
+local capabilities = {
+    io = function(env)
+        env.io = io
+    end,
+    os = function(env)
+        env.os = os
+    end,
+    metatables = function(env)
+        env.rawset = rawset
+        env.setmetatable = setmetatable
+    end,
+}
+
+-- Once a handler has gone over its time limit the error must reach the
+-- server, so protected calls can't be used to carry on running.
+local function rethrow_if_over_budget(ok, ...)
+    if not ok and _qc.overBudget() then
+        error(..., 0)
     end
-    local message = assert(to_chat(message))
-    table.insert(_qc.responses, {type = "message", player = player, message = message})
+    return ok, ...
 end
 
-function server.broadcast(message)
-    local message = assert(to_chat(message))
-    table.insert(_qc.responses, { type = "broadcast", message = message })
+local function protected(f)
+    return function(...)
+        return rethrow_if_over_budget(f(...))
+    end
 end
 
-function server.sendPluginMessage(player, channel, data)
-    if type(player) ~= "string" then
-        error("player must be a string")
+-- Where a module might be found under each search directory
+local module_files = { "%s/%s.lua", "%s/%s/init.lua" }
+
+-- A `require` that loads modules from the directories in `roots` into the
+-- plugin's own environment, with its own set of loaded modules. `roots` is
+-- only held here, so plugins can't point `require` at other files.
+local function make_require(env, package, roots)
+    return function(name)
+        name = tostring(name)
+        if package.loaded[name] ~= nil then
+            return package.loaded[name]
+        end
+        -- module names are dot-separated words, without path separators or
+        -- `..`, so they can't name a file outside the roots
+        if name:find("..", 1, true) or not name:match("^[%w_%-][%w_%-%.]*$") then
+            error("invalid module name '" .. name .. "'", 2)
+        end
+        local relative = name:gsub("%.", "/")
+        local path
+        local tried = {}
+        for _, root in ipairs(roots) do
+            for _, pattern in ipairs(module_files) do
+                local candidate = pattern:format(root, relative)
+                assert(candidate:sub(1, #root + 1) == root .. "/", "module path outside of search directory")
+                local file = io.open(candidate, "r")
+                if file ~= nil then
+                    file:close()
+                    path = candidate
+                    break
+                end
+                table.insert(tried, "\n\tno file '" .. candidate .. "'")
+            end
+            if path ~= nil then
+                break
+            end
+        end
+        if path == nil then
+            error("module '" .. name .. "' not found:" .. table.concat(tried), 2)
+        end
+        local chunk, load_err = loadfile(path, "t", env)
+        if chunk == nil then
+            error("error loading module '" .. name .. "' from file '" .. path .. "':\n\t" .. load_err, 2)
+        end
+        local result = chunk(name, path)
+        if result == nil then
+            result = true
+        end
+        package.loaded[name] = result
+        return result
+    end
+end
+
+-- Create a fresh environment for the plugin with the ID `id`, containing
+-- only the parts of the standard library that can't touch the filesystem,
+-- other processes, or other plugins' state. Also returns the plugin's `owner`
+-- table, whose `id` is set once the plugin is loaded. Modules can be required
+-- from the plugin's own directory (if it has one) and from the shared
+-- library directory.
+function _qc.createEnv(id, root, lib)
+    local env = {}
+    local owner = {}
+    for _, name in ipairs(safe_globals) do
+        env[name] = _G[name]
+    end
+    for _, name in ipairs(safe_libs) do
+        env[name] = copy(_G[name])
     end
-    if type(channel) ~= "string" then
-        error("channel must be a string")
+    env.pcall = protected(pcall)
+    env.xpcall = protected(xpcall)
+    env.coroutine.resume = protected(coroutine.resume)
+    env.os = {}
+    for _, name in ipairs(safe_os) do
+        env.os[name] = os[name]
     end
-    table.insert(_qc.responses, {type = "plugin_message", player = player, channel = channel, data = data})
+    env.load = function(chunk, chunkname, _, chunkenv)
+        return load(chunk, chunkname, "t", chunkenv or env)
+    end
+    env.server = server_proxy(owner, id)
+    local roots = { lib }
+    if root ~= nil then
+        table.insert(roots, 1, root)
+    end
+    env.package = { loaded = {} }
+    for _, name in ipairs(safe_libs) do
+        env.package.loaded[name] = env[name]
+    end
+    env.require = make_require(env, env.package, roots)
+    env._G = env
+    return env, owner
 end
 
-function server.disconnect(player, reason)
-    local reason = assert(to_chat(reason, { translate = "multiplayer.disconnect.generic" }))
-    table.insert(_qc.responses, { type = "disconnect", player = player, reason = reason })
+function _qc.grant(env, requested)
+    for _, name in ipairs(requested) do
+        local grant = capabilities[name]
+        if grant == nil then
+            error("unknown capability '" .. tostring(name) .. "'", 0)
+        end
+        grant(env)
+    end
 end
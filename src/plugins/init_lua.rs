@@ -1,9 +1,11 @@
 use log::{info, warn, trace, error, debug};
-use mlua::{Lua, chunk};
-use crate::VERSION;
+use mlua::{Lua, Table, Value, chunk};
+use crate::{VERSION, network::{set_block, SharedPlayers}, protocol::clientbound::SystemChatMessage, world::SharedWorlds};
 
+use super::player::{to_chat, player_key, with_player, default_kick_reason};
 
-pub fn init(lua: &Lua) -> Result<(), mlua::Error> {
+
+pub fn init(lua: &Lua, players: SharedPlayers, worlds: SharedWorlds) -> Result<(), mlua::Error> {
     macro_rules! log_any {
         ($level:tt) => {
             lua.create_function(|_, args: (String, String)| {
@@ -17,7 +19,72 @@ pub fn init(lua: &Lua) -> Result<(), mlua::Error> {
     let log_info = log_any!(info)?;
     let log_warn = log_any!(warn)?;
     let log_error = log_any!(error)?;
-    lua.load(include_str!("init.lua")).exec()?;
+
+    let pl = players.clone();
+    let get_player = lua.create_function(move |lua, key: String| {
+        let Some(uuid) = pl.borrow().find(&key).map(|p| p.player.uuid) else {
+            return Ok(mlua::Nil)
+        };
+        let server: Table = lua.globals().get("server")?;
+        let players: Table = server.get("players")?;
+        players.get(uuid.to_string())
+    })?;
+    let pl = players.clone();
+    let send_message = lua.create_function(move |lua, (player, message): (Value, Value)| {
+        let message = to_chat(lua, message, None)?;
+        Ok(with_player(&pl, &player_key(player)?, |p| p.send_message(message)))
+    })?;
+    let pl = players.clone();
+    let send_plugin_message = lua.create_function(move |_, (player, channel, data): (Value, String, Vec<u8>)| {
+        Ok(with_player(&pl, &player_key(player)?, |p| p.send_plugin_message(channel, data)))
+    })?;
+    let pl = players.clone();
+    let disconnect = lua.create_function(move |lua, (player, reason): (Value, Value)| {
+        let reason = to_chat(lua, reason, Some(default_kick_reason()))?;
+        Ok(with_player(&pl, &player_key(player)?, |p| p.disconnect(reason)))
+    })?;
+    let wl = worlds.clone();
+    let get_worlds = lua.create_function(move |_, ()| {
+        Ok(wl.borrow().names().map(str::to_owned).collect::<Vec<_>>())
+    })?;
+    let pl = players.clone();
+    let set_block = lua.create_function(move |lua, (world, x, y, z, state): (String, i32, i32, i32, Value)| {
+        let mut worlds = worlds.borrow_mut();
+        let state = match state {
+            Value::String(name) => match worlds.blocks().state(name.to_str()?) {
+                Some(state) => state,
+                None => return Ok((false, Some(format!("unknown block '{}'", name.to_str()?)))),
+            },
+            state => lua.unpack(state)?,
+        };
+        Ok(match set_block(&pl.borrow(), &mut worlds, &world, (x, y, z), state) {
+            Ok(()) => (true, None),
+            Err(e) => (false, Some(e)),
+        })
+    })?;
+    let pl = players.clone();
+    let get_world_players = lua.create_function(move |lua, name: String| {
+        let server: Table = lua.globals().get("server")?;
+        let online: Table = server.get("players")?;
+        let uuids: Vec<String> = pl.borrow().iter()
+            .filter(|p| p.world == name)
+            .map(|p| p.player.uuid.to_string())
+            .collect();
+        let list = lua.create_table()?;
+        for uuid in uuids {
+            if let Some(player) = online.get::<_, Option<Value>>(uuid)? {
+                list.push(player)?;
+            }
+        }
+        Ok(list)
+    })?;
+    let pl = players;
+    let broadcast = lua.create_function(move |lua, message: Value| {
+        let message = to_chat(lua, message, None)?;
+        Ok(pl.borrow().broadcast(SystemChatMessage { message, overlay: false }))
+    })?;
+
+    lua.load(include_str!("init.lua")).set_name("=init.lua")?.exec()?;
     lua.load(chunk!{
         function server.initLogger(plugin)
             local id = "pl::" .. assert(plugin["id"])
@@ -30,8 +97,16 @@ pub fn init(lua: &Lua) -> Result<(), mlua::Error> {
             }
         end
 
+        server.getPlayer = $get_player
+        server.sendMessage = $send_message
+        server.sendPluginMessage = $send_plugin_message
+        server.disconnect = $disconnect
+        server.broadcast = $broadcast
+        server.getWorlds = $get_worlds
+        server.getWorldPlayers = $get_world_players
+        server.setBlock = $set_block
         server.version = $VERSION
     }).exec()?;
-        
+
     Ok(())
 }
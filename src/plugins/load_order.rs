@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use log::error;
+
+use super::metadata::Metadata;
+
+/// Sort plugins so that every plugin comes after the plugins it depends on.
+/// Plugins with missing dependencies or that are part of a dependency cycle
+/// are reported and left out. Ties are broken by plugin ID so the order does
+/// not depend on the order of the directory listing. `loaded` holds the IDs
+/// of plugins that are already loaded (native plugins), which satisfy
+/// dependencies without needing to be sorted. Each plugin's metadata is
+/// paired with whatever is needed to load it later.
+pub fn sort_plugins<T>(plugins: Vec<(Metadata, T)>, loaded: &BTreeSet<String>) -> Vec<(Metadata, T)> {
+    let mut plugins: BTreeMap<String, (Metadata, T)> = plugins.into_iter()
+        .map(|pl| (pl.0.id.clone(), pl))
+        .collect();
+
+    // Repeatedly drop plugins whose hard dependencies are not available,
+    // since dropping one plugin may leave another without a dependency.
+    loop {
+        let missing: Vec<(String, String)> = plugins.values()
+            .filter_map(|(pl, _)| pl.depends.iter()
+                .find(|dep| !plugins.contains_key(*dep) && !loaded.contains(*dep))
+                .map(|dep| (pl.id.clone(), dep.clone())))
+            .collect();
+        if missing.is_empty() {
+            break
+        }
+        for (id, dep) in missing {
+            error!("Not loading plugin '{}': missing dependency '{}'", id, dep);
+            plugins.remove(&id);
+        }
+    }
+
+    // (hard dependencies, soft dependencies) for each plugin
+    let mut dependencies: Dependencies = BTreeMap::new();
+    for (pl, _) in plugins.values() {
+        // a plugin that depends on itself is a cycle of one
+        let hard = pl.depends.iter()
+            .filter(|dep| !loaded.contains(*dep))
+            .map(String::as_str)
+            .collect();
+        let soft = pl.soft_depends.iter()
+            .filter(|dep| plugins.contains_key(*dep) && **dep != pl.id)
+            .map(String::as_str)
+            .collect();
+        dependencies.insert(pl.id.as_str(), (hard, soft));
+    }
+
+    let mut order: Vec<String> = Vec::new();
+    let mut done: BTreeSet<&str> = BTreeSet::new();
+    while done.len() < dependencies.len() {
+        let pending = || dependencies.iter().filter(|(id, _)| !done.contains(*id));
+        // Prefer a plugin whose dependencies are all loaded. If there is none,
+        // a cycle only involving soft dependencies can be broken by ignoring them.
+        let ready = pending()
+            .find(|(_, (hard, soft))| hard.iter().chain(soft.iter()).all(|dep| done.contains(dep)))
+            .or_else(|| pending().find(|(_, (hard, _))| hard.iter().all(|dep| done.contains(dep))));
+        match ready {
+            Some((id, _)) => {
+                let id = *id;
+                done.insert(id);
+                order.push(id.to_owned());
+            }
+            None => {
+                report_cycles(&dependencies, &done);
+                break
+            }
+        }
+    }
+
+    order.into_iter()
+        .filter_map(|id| plugins.remove(&id))
+        .collect()
+}
+
+type Dependencies<'a> = BTreeMap<&'a str, (BTreeSet<&'a str>, BTreeSet<&'a str>)>;
+
+/// Report the plugins that couldn't be sorted: those in a dependency cycle,
+/// and those that depend on one.
+fn report_cycles(dependencies: &Dependencies, done: &BTreeSet<&str>) {
+    let stuck: BTreeSet<&str> = dependencies.keys().copied().filter(|id| !done.contains(id)).collect();
+    let (cycles, dependents): (Vec<&str>, Vec<&str>) = stuck.iter()
+        .partition(|id| in_cycle(dependencies, &stuck, id));
+    error!("Not loading plugins with circular dependencies: {}", cycles.join(", "));
+    for id in dependents {
+        if let Some(dep) = dependencies[id].0.iter().find(|dep| stuck.contains(*dep)) {
+            error!("Not loading plugin '{}': its dependency '{}' can't be loaded", id, dep);
+        }
+    }
+}
+
+/// Whether `id` can reach itself by following hard dependencies between
+/// the plugins in `stuck`.
+fn in_cycle(dependencies: &Dependencies, stuck: &BTreeSet<&str>, id: &str) -> bool {
+    let mut seen = BTreeSet::new();
+    let mut next = vec![id];
+    while let Some(current) = next.pop() {
+        for dep in &dependencies[current].0 {
+            if *dep == id {
+                return true
+            }
+            if stuck.contains(dep) && seen.insert(*dep) {
+                next.push(dep);
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin(id: &str, depends: &[&str], soft_depends: &[&str]) -> (Metadata, ()) {
+        let meta = Metadata {
+            id: id.to_owned(),
+            depends: depends.iter().map(|d| d.to_string()).collect(),
+            soft_depends: soft_depends.iter().map(|d| d.to_string()).collect(),
+            ..Metadata::default()
+        };
+        (meta, ())
+    }
+
+    fn sorted(plugins: Vec<(Metadata, ())>, loaded: &[&str]) -> Vec<String> {
+        let loaded = loaded.iter().map(|id| id.to_string()).collect();
+        sort_plugins(plugins, &loaded).into_iter().map(|(meta, _)| meta.id).collect()
+    }
+
+    #[test]
+    fn dependency_order() {
+        let plugins = vec![
+            plugin("a", &["c"], &[]),
+            plugin("b", &[], &[]),
+            plugin("c", &["d", "native"], &[]),
+            plugin("d", &[], &[]),
+        ];
+        assert_eq!(sorted(plugins, &["native"]), ["b", "d", "c", "a"]);
+    }
+
+    #[test]
+    fn soft_dependencies() {
+        let plugins = vec![
+            plugin("a", &[], &["c", "missing"]),
+            plugin("b", &[], &[]),
+            plugin("c", &[], &[]),
+        ];
+        assert_eq!(sorted(plugins, &[]), ["b", "c", "a"]);
+        // a cycle of soft dependencies is broken rather than dropped
+        let plugins = vec![plugin("a", &[], &["b"]), plugin("b", &[], &["a"])];
+        assert_eq!(sorted(plugins, &[]), ["a", "b"]);
+    }
+
+    #[test]
+    fn missing_dependencies() {
+        let plugins = vec![
+            plugin("a", &["missing"], &[]),
+            plugin("b", &["a"], &[]),
+            plugin("c", &[], &[]),
+        ];
+        assert_eq!(sorted(plugins, &[]), ["c"]);
+    }
+
+    #[test]
+    fn cycles() {
+        let plugins = vec![
+            plugin("a", &["b"], &[]),
+            plugin("b", &["a"], &[]),
+            plugin("c", &[], &[]),
+        ];
+        assert_eq!(sorted(plugins, &[]), ["c"]);
+        let plugins = vec![plugin("a", &["a"], &[]), plugin("b", &[], &[])];
+        assert_eq!(sorted(plugins, &[]), ["b"]);
+    }
+
+    #[test]
+    fn cycle_dependents() {
+        let plugins = vec![
+            plugin("a", &["b"], &[]),
+            plugin("b", &["a"], &[]),
+            plugin("c", &["a"], &[]),
+            plugin("d", &["d"], &[]),
+        ];
+        assert!(sorted(plugins, &[]).is_empty());
+        // c is left out for depending on a cycle, but isn't part of one
+        let dependencies: Dependencies = BTreeMap::from([
+            ("a", (BTreeSet::from(["b"]), BTreeSet::new())),
+            ("b", (BTreeSet::from(["a"]), BTreeSet::new())),
+            ("c", (BTreeSet::from(["a"]), BTreeSet::new())),
+            ("d", (BTreeSet::from(["d"]), BTreeSet::new())),
+        ]);
+        let stuck: BTreeSet<&str> = dependencies.keys().copied().collect();
+        let cycles: Vec<&str> = stuck.iter().copied().filter(|id| in_cycle(&dependencies, &stuck, id)).collect();
+        assert_eq!(cycles, ["a", "b", "d"]);
+    }
+}
@@ -0,0 +1,313 @@
+use std::{path::Path, fs};
+
+use log::warn;
+use mlua::{DeserializeOptions, Function, Table, Lua, LuaSerdeExt, SerializeOptions, Value};
+use serde_json::Value as Json;
+
+use crate::{config::GameMode, network::{BlockEvent, JoinSettings, Player}};
+
+use super::{metadata::Metadata, player::lua_player, plugin::{Plugin, Context, CommandRegistry, HandlerResult}};
+
+pub struct EventHandlers<'lua> {
+    pub init: Option<Function<'lua>>,
+    pub register_commands: Option<Function<'lua>>,
+    pub pre_login: Option<Function<'lua>>,
+    pub player_join: Option<Function<'lua>>,
+    pub player_leave: Option<Function<'lua>>,
+    pub chat_message: Option<Function<'lua>>,
+    pub command: Option<Function<'lua>>,
+    pub plugin_message: Option<Function<'lua>>,
+    pub block_interact: Option<Function<'lua>>,
+    pub block_break: Option<Function<'lua>>,
+    pub block_place: Option<Function<'lua>>,
+    pub tick: Option<Function<'lua>>,
+}
+
+pub struct LuaPlugin<'lua> {
+    lua: &'lua Lua,
+    pub meta: Metadata,
+    pub event_handlers: EventHandlers<'lua>,
+    /// Identifies the plugin to the Lua side of the server, for listeners
+    /// and services it registers. Its `id` is only set once the plugin has
+    /// been accepted for loading.
+    pub owner: Table<'lua>,
+}
+
+impl <'lua> LuaPlugin<'lua> {
+    /// Run a plugin's code. If the plugin has a metadata file, its metadata
+    /// has already been checked and the capabilities it requests are granted
+    /// before the code runs. Otherwise the metadata is read from the plugin
+    /// table, with `default_id` as the ID if the table doesn't set one.
+    pub fn load(path: &Path, default_id: &str, file_meta: Option<Metadata>, plugins_dir: &Path, lua: &'lua Lua) -> Result<Self, Box<dyn std::error::Error>> {
+        let qc: Table = lua.globals().get("_qc")?;
+        // directory plugins can require modules from their own directory
+        let root = path.parent()
+            .filter(|dir| *dir != plugins_dir)
+            .map(|dir| dir.to_string_lossy().into_owned());
+        let lib = plugins_dir.join("lib").to_string_lossy().into_owned();
+        let id = file_meta.as_ref().map_or(default_id, |meta| meta.id.as_str());
+        let (env, owner): (Table, Table) = qc.get::<_, Function>("createEnv")?.call((id, root, lib))?;
+        if let Some(meta) = &file_meta {
+            qc.get::<_, Function>("grant")?.call::<_, ()>((env.clone(), meta.capabilities.clone()))?;
+        }
+        let chunk = lua.load(path).set_environment(env.clone())?;
+        let module: Table = chunk.eval()?;
+
+        let options = DeserializeOptions::new().deny_unsupported_types(false);
+        let mut table_meta: Metadata = lua.from_value_with(Value::Table(module.clone()), options)
+            .map_err(|e| format!("invalid plugin table: {}", e))?;
+        let table_capabilities = std::mem::take(&mut table_meta.capabilities);
+        let mut meta = match file_meta {
+            Some(meta) => {
+                let differences = meta.differences(&table_meta);
+                if !differences.is_empty() {
+                    warn!("Plugin {}: using {} from its metadata file, which differ from its plugin table",
+                        meta.id, differences.join(", "));
+                }
+                meta
+            }
+            None => table_meta.with_defaults(default_id)?,
+        };
+        // capabilities in the plugin table can only be granted now that it
+        // has been returned, so they are available to event handlers
+        let extra: Vec<String> = table_capabilities.into_iter()
+            .filter(|capability| !meta.capabilities.contains(capability))
+            .collect();
+        qc.get::<_, Function>("grant")?.call::<_, ()>((env, extra.clone()))?;
+        meta.capabilities.extend(extra);
+        module.set("id", meta.id.as_str())?;
+        module.set("name", meta.name.as_str())?;
+        module.set("version", meta.version.as_str())?;
+        module.set("description", meta.description.as_deref())?;
+        module.set("authors", meta.authors.clone())?;
+        if let Some(defaults) = module.get::<_, Option<Table>>("defaultConfig")? {
+            let defaults: Json = lua.from_value(mlua::Value::Table(defaults))?;
+            let config = load_config(&plugins_dir.join(&meta.id).join("config.json"), defaults)?;
+            module.set("config", lua.to_value_with(&config, SerializeOptions::new().serialize_none_to_null(false))?)?;
+        }
+
+        let init: Option<Function<'lua>> = module.get("init").ok();
+        let register_commands: Option<Function<'lua>> = module.get("registerCommands").ok();
+        let pre_login: Option<Function<'lua>> = module.get("preLogin").ok();
+        let player_join: Option<Function<'lua>> = module.get("playerJoin").ok();
+        let player_leave: Option<Function<'lua>> = module.get("playerLeave").ok();
+        let chat_message: Option<Function<'lua>> = module.get("chatMessage").ok();
+        let command: Option<Function<'lua>> = module.get("command").ok();
+        let plugin_message: Option<Function<'lua>> = module.get("pluginMessage").ok();
+        let block_interact: Option<Function<'lua>> = module.get("blockInteract").ok();
+        let block_break: Option<Function<'lua>> = module.get("blockBreak").ok();
+        let block_place: Option<Function<'lua>> = module.get("blockPlace").ok();
+        let tick: Option<Function<'lua>> = module.get("tick").ok();
+
+        let event_handlers = EventHandlers {
+            init,
+            register_commands,
+            pre_login,
+            player_join,
+            player_leave,
+            chat_message,
+            command,
+            plugin_message,
+            block_interact,
+            block_break,
+            block_place,
+            tick,
+        };
+        Ok(LuaPlugin {
+            lua,
+            meta,
+            event_handlers,
+            owner,
+        })
+    }
+}
+
+impl <'lua> LuaPlugin<'lua> {
+    /// Call a block event handler with the player and a table of the event,
+    /// then read `cancelled` and `state` back from the table.
+    fn block_event(&self, func: Option<&Function<'lua>>, ctx: &Context, player: &Player, event: &mut BlockEvent) -> HandlerResult {
+        let Some(func) = func else {
+            return Ok(())
+        };
+        let table = self.lua.create_table()?;
+        let (x, y, z) = event.position;
+        table.set("world", event.world.as_str())?;
+        table.set("x", x)?;
+        table.set("y", y)?;
+        table.set("z", z)?;
+        table.set("face", event.face.map(|f| f.name()))?;
+        table.set("state", event.state)?;
+        table.set("cancelled", event.cancelled)?;
+        func.call::<_, ()>((lua_player(self.lua, player, ctx)?, table.clone()))?;
+
+        event.cancelled = table.get::<_, Option<bool>>("cancelled")?.unwrap_or(false);
+        event.state = match table.get("state")? {
+            Value::Nil => None,
+            Value::String(name) => {
+                let name = name.to_str()?;
+                let state = ctx.worlds().borrow().blocks().state(name);
+                Some(state.ok_or_else(|| format!("unknown block '{}'", name))?)
+            }
+            value => Some(self.lua.unpack(value)?),
+        };
+        Ok(())
+    }
+}
+
+impl <'lua> Plugin for LuaPlugin<'lua> {
+    fn id(&self) -> &str { &self.meta.id }
+    fn name(&self) -> &str { &self.meta.name }
+    fn version(&self) -> &str { &self.meta.version }
+    fn description(&self) -> Option<&str> { self.meta.description.as_deref() }
+    fn authors(&self) -> &[String] { &self.meta.authors }
+    fn capabilities(&self) -> &[String] { &self.meta.capabilities }
+
+    fn init(&mut self, _: &Context) -> HandlerResult {
+        if let Some(func) = &self.event_handlers.init {
+            func.call::<_, ()>(())?;
+        }
+        Ok(())
+    }
+
+    fn register_commands(&mut self, registry: &CommandRegistry) -> HandlerResult {
+        if let Some(func) = &self.event_handlers.register_commands {
+            let registry = registry.clone();
+            let add_command = self.lua.create_function(move |_, name: String| {
+                registry.add_command(&name);
+                Ok(())
+            })?;
+            let table = self.lua.create_table()?;
+            table.set("addCommand", add_command)?;
+            func.call::<_, ()>(table)?;
+        }
+        Ok(())
+    }
+
+    fn pre_login(&mut self, ctx: &Context, player: &Player, settings: &mut JoinSettings) -> HandlerResult {
+        if let Some(func) = &self.event_handlers.pre_login {
+            let table = self.lua.create_table()?;
+            table.set("world", settings.world.as_str())?;
+            let (x, y, z) = settings.position;
+            let (yaw, pitch) = settings.rotation;
+            table.set("x", x)?;
+            table.set("y", y)?;
+            table.set("z", z)?;
+            table.set("yaw", yaw)?;
+            table.set("pitch", pitch)?;
+            table.set("gamemode", settings.gamemode.name())?;
+            table.set("hardcore", settings.hardcore)?;
+            table.set("viewDistance", settings.view_distance)?;
+            table.set("reducedDebugInfo", settings.reduced_debug_info)?;
+            table.set("respawnScreen", settings.respawn_screen)?;
+            func.call::<_, ()>((lua_player(self.lua, player, ctx)?, table.clone()))?;
+
+            let gamemode: String = table.get("gamemode")?;
+            let view_distance: u8 = table.get("viewDistance")?;
+            if !(2..=32).contains(&view_distance) {
+                Err(format!("viewDistance {} must be between 2 and 32", view_distance))?
+            }
+            *settings = JoinSettings {
+                world: table.get("world")?,
+                position: (table.get("x")?, table.get("y")?, table.get("z")?),
+                rotation: (table.get("yaw")?, table.get("pitch")?),
+                gamemode: gamemode.parse::<GameMode>()?,
+                hardcore: table.get("hardcore")?,
+                view_distance,
+                reduced_debug_info: table.get("reducedDebugInfo")?,
+                respawn_screen: table.get("respawnScreen")?,
+            };
+        }
+        Ok(())
+    }
+
+    fn player_join(&mut self, ctx: &Context, player: &Player, replay: bool) -> HandlerResult {
+        if let Some(func) = &self.event_handlers.player_join {
+            func.call::<_, ()>((lua_player(self.lua, player, ctx)?, replay))?;
+        }
+        Ok(())
+    }
+
+    fn player_leave(&mut self, ctx: &Context, player: &Player) -> HandlerResult {
+        if let Some(func) = &self.event_handlers.player_leave {
+            func.call::<_, ()>(lua_player(self.lua, player, ctx)?)?;
+        }
+        Ok(())
+    }
+
+    fn chat_message(&mut self, ctx: &Context, player: &Player, message: &str) -> HandlerResult {
+        if let Some(func) = &self.event_handlers.chat_message {
+            func.call::<_, ()>((message, lua_player(self.lua, player, ctx)?))?;
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, ctx: &Context, player: &Player, command: &str, args: &str) -> HandlerResult {
+        match &self.event_handlers.command {
+            Some(func) => func.call::<_, ()>((command, args, lua_player(self.lua, player, ctx)?))?,
+            None => warn!("Plugin {} registered a command but no command handler was found", self.meta.id),
+        }
+        Ok(())
+    }
+
+    fn plugin_message(&mut self, ctx: &Context, player: &Player, channel: &str, data: &[u8]) -> HandlerResult {
+        if let Some(func) = &self.event_handlers.plugin_message {
+            func.call::<_, ()>((channel, data, lua_player(self.lua, player, ctx)?))?;
+        }
+        Ok(())
+    }
+
+    fn block_interact(&mut self, ctx: &Context, player: &Player, event: &mut BlockEvent) -> HandlerResult {
+        self.block_event(self.event_handlers.block_interact.as_ref(), ctx, player, event)
+    }
+
+    fn block_break(&mut self, ctx: &Context, player: &Player, event: &mut BlockEvent) -> HandlerResult {
+        self.block_event(self.event_handlers.block_break.as_ref(), ctx, player, event)
+    }
+
+    fn block_place(&mut self, ctx: &Context, player: &Player, event: &mut BlockEvent) -> HandlerResult {
+        self.block_event(self.event_handlers.block_place.as_ref(), ctx, player, event)
+    }
+
+    fn tick(&mut self, _: &Context) -> HandlerResult {
+        if let Some(func) = &self.event_handlers.tick {
+            func.call::<_, ()>(())?;
+        }
+        Ok(())
+    }
+}
+
+/// Read a plugin's config file, filling in anything it doesn't set from the
+/// plugin's defaults. The file is created with the defaults if it doesn't
+/// exist yet.
+fn load_config(path: &Path, defaults: Json) -> Result<Json, Box<dyn std::error::Error>> {
+    fn merge(base: &mut Json, over: Json) {
+        match (base, over) {
+            (Json::Object(base), Json::Object(over)) => {
+                for (key, value) in over {
+                    match base.get_mut(&key) {
+                        Some(existing) => merge(existing, value),
+                        None => { base.insert(key, value); }
+                    }
+                }
+            }
+            (base, over) => *base = over,
+        }
+    }
+    match fs::read(path) {
+        Ok(bytes) => {
+            let user: Json = serde_json::from_slice(&bytes)
+                .map_err(|e| format!("invalid config file {}: {}", path.display(), e))?;
+            let mut config = defaults;
+            merge(&mut config, user);
+            Ok(config)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(path, serde_json::to_vec_pretty(&defaults)?)?;
+            Ok(defaults)
+        }
+        Err(e) => Err(format!("couldn't read config file {}: {}", path.display(), e))?,
+    }
+}
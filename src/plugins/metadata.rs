@@ -0,0 +1,175 @@
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+
+/// Information about a plugin, read from a JSON file next to its code or,
+/// for Lua plugins without one, from the plugin table.
+#[derive(Deserialize, Default, Clone, PartialEq, Debug)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Metadata {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub authors: Vec<String>,
+    pub depends: Vec<String>,
+    pub soft_depends: Vec<String>,
+    pub api_version: Option<String>,
+    pub capabilities: Vec<String>,
+}
+
+impl Metadata {
+    /// Read metadata from `path`, or `None` if there is no such file.
+    pub fn read(path: &Path) -> Result<Option<Self>, String> {
+        match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(|e| format!("invalid metadata file {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("couldn't read {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Fill in the fields that weren't given: a plugin without an ID gets
+    /// `default_id` as its ID and name. Fails if the ID isn't valid.
+    pub fn with_defaults(mut self, default_id: &str) -> Result<Self, String> {
+        if self.id.is_empty() {
+            self.id = default_id.to_owned();
+        }
+        if !valid_id(&self.id) {
+            return Err(format!("invalid plugin ID '{}'", self.id))
+        }
+        if self.name.is_empty() {
+            self.name = self.id.clone();
+        }
+        if self.version.is_empty() {
+            self.version = "?".to_owned();
+        }
+        Ok(self)
+    }
+
+    /// The fields that `other` sets to something different, used to warn
+    /// about plugin tables that disagree with their metadata file. Fields
+    /// that `other` leaves unset don't count, and neither do capabilities,
+    /// which are combined rather than overridden.
+    pub fn differences(&self, other: &Metadata) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        let mut check = |name, set: bool, same: bool| if set && !same { fields.push(name) };
+        check("id", !other.id.is_empty(), self.id == other.id);
+        check("name", !other.name.is_empty(), self.name == other.name);
+        check("version", !other.version.is_empty(), self.version == other.version);
+        check("description", other.description.is_some(), self.description == other.description);
+        check("authors", !other.authors.is_empty(), self.authors == other.authors);
+        check("depends", !other.depends.is_empty(), self.depends == other.depends);
+        check("softDepends", !other.soft_depends.is_empty(), self.soft_depends == other.soft_depends);
+        check("apiVersion", other.api_version.is_some(), self.api_version == other.api_version);
+        fields
+    }
+}
+
+/// Whether `id` can be used as a plugin ID. IDs are used as directory names
+/// for plugin config and storage, so they can't contain path separators or
+/// start with a dot.
+pub fn valid_id(id: &str) -> bool {
+    !id.is_empty() && !id.starts_with('.')
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+}
+
+/// Check whether a plugin written against `required` can run on a server of
+/// version `server`. Versions are compared semver-style: the major version
+/// (or the minor version while the major version is 0) must match, and the
+/// server must be at least as new as the required version.
+pub fn api_compatible(required: &str, server: &str) -> Result<bool, String> {
+    fn parse(v: &str) -> Result<[u32; 3], String> {
+        let mut parts = [0; 3];
+        let mut count = 0;
+        for (i, part) in v.split('.').enumerate() {
+            if i >= 3 {
+                return Err(format!("invalid version '{}'", v))
+            }
+            parts[i] = part.parse().map_err(|_| format!("invalid version '{}'", v))?;
+            count += 1;
+        }
+        if count == 0 {
+            return Err(format!("invalid version '{}'", v))
+        }
+        Ok(parts)
+    }
+    let required = parse(required)?;
+    let server = parse(server)?;
+    let same_series = if required[0] == 0 {
+        server[0] == 0 && server[1] == required[1]
+    } else {
+        server[0] == required[0]
+    };
+    Ok(same_series && server >= required)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugin_ids() {
+        for id in ["mcchat", "my_plugin", "plugin-2", "v1.0"] {
+            assert!(valid_id(id), "'{}' was rejected", id);
+        }
+        for id in ["", ".", "..", "../../x", "a/b", "a\\b", ".hidden", "has space"] {
+            assert!(!valid_id(id), "'{}' was accepted", id);
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let meta = Metadata::default().with_defaults("example").unwrap();
+        assert_eq!((meta.id.as_str(), meta.name.as_str(), meta.version.as_str()), ("example", "example", "?"));
+        let meta = Metadata { id: "other".into(), name: "Other".into(), ..Metadata::default() };
+        assert_eq!(meta.with_defaults("example").unwrap().id, "other");
+        let meta = Metadata { id: "../x".into(), ..Metadata::default() };
+        assert!(meta.with_defaults("example").is_err());
+    }
+
+    #[test]
+    fn differences() {
+        let file = Metadata { id: "foo".into(), name: "Foo".into(), version: "1.0".into(), ..Metadata::default() };
+        // unset fields are not differences
+        assert!(file.differences(&Metadata::default()).is_empty());
+        assert!(file.differences(&Metadata { id: "foo".into(), ..Metadata::default() }).is_empty());
+        let table = Metadata {
+            id: "bar".into(),
+            version: "1.0".into(),
+            description: Some("a plugin".into()),
+            ..Metadata::default()
+        };
+        assert_eq!(file.differences(&table), ["id", "description"]);
+    }
+
+    #[test]
+    fn exact_version() {
+        assert_eq!(api_compatible("0.1.0", "0.1.0"), Ok(true));
+        assert_eq!(api_compatible("1.2.3", "1.2.3"), Ok(true));
+        assert_eq!(api_compatible("0.1", "0.1.0"), Ok(true));
+    }
+
+    #[test]
+    fn compatible_range() {
+        // newer servers in the same series are accepted
+        assert_eq!(api_compatible("1.2", "1.5.1"), Ok(true));
+        assert_eq!(api_compatible("0.1", "0.1.7"), Ok(true));
+        // older servers are not
+        assert_eq!(api_compatible("1.2", "1.1.9"), Ok(false));
+        assert_eq!(api_compatible("0.1.3", "0.1.2"), Ok(false));
+        // neither are other major versions, or other minor versions before 1.0
+        assert_eq!(api_compatible("1.0", "2.0.0"), Ok(false));
+        assert_eq!(api_compatible("0.1", "0.2.0"), Ok(false));
+        assert_eq!(api_compatible("0.1", "1.0.0"), Ok(false));
+    }
+
+    #[test]
+    fn malformed_version() {
+        for bad in ["", "1..2", "v1.0", "1.0.0.0", "1.x", "-1.0", "1.0 "] {
+            assert!(api_compatible(bad, "0.1.0").is_err(), "'{}' was accepted", bad);
+            assert!(api_compatible("0.1", bad).is_err(), "'{}' was accepted", bad);
+        }
+    }
+}
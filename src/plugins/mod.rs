@@ -1,195 +1,738 @@
-use std::{fs::read_dir, rc::Rc, cell::RefCell, collections::HashMap};
+use std::{fs::read_dir, rc::Rc, cell::{RefCell, Cell}, collections::{HashMap, BTreeSet}, path::{Path, PathBuf}, time::{SystemTime, Instant, Duration}, ops::Deref, panic::{catch_unwind, AssertUnwindSafe}};
 
-use log::{warn, info};
-use mlua::{Lua, Table, LuaSerdeExt};
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
+use log::{warn, info, error};
+use mlua::{Lua, Table, Function, HookTriggers, Value};
 
-use crate::{network::Player, protocol::command::Commands};
+use crate::{network::{BlockEvent, JoinSettings, Player, PlayerRegistry, SharedPlayers}, protocol::command::Commands, config::Config, world::{SharedWorlds, WorldRegistry}, VERSION};
 
-use self::plugin::Plugin;
+use self::lua_plugin::LuaPlugin;
+use self::metadata::Metadata;
+use self::player::LuaPlayer;
+use self::storage::{StorageRegistry, LuaStorage};
+
+pub use self::plugin::{Plugin, Context, CommandRegistry, HandlerResult};
 
 mod init_lua;
+mod load_order;
+mod lua_plugin;
+mod metadata;
+mod player;
 mod plugin;
-
-#[derive(Serialize, Deserialize)]
-#[serde(tag="type")]
-pub enum Response {
-    #[serde(rename = "message")]
-    Message { player: String, message: serde_json::Value },
-    #[serde(rename = "plugin_message")]
-    PluginMessage { player: String, channel: String, data: Vec<u8> },
-    #[serde(rename = "broadcast")]
-    Broadcast { message: serde_json::Value },
-    #[serde(rename = "disconnect")]
-    Disconnect { player: String, reason: serde_json::Value },
-}
-
+mod storage;
+#[cfg(feature = "wasm")]
+mod wasm;
 
 pub struct Plugins<'lua> {
     lua: &'lua Lua,
-    plugins: Vec<Plugin<'lua>>,
+    /// Native plugins, in the order they were registered, followed by Lua
+    /// plugins in load order
+    plugins: Vec<LoadedPlugin<'lua>>,
+    ctx: Context,
+    dir: PathBuf,
     cmd_owners: HashMap<String, usize>,
+    players: SharedPlayers,
+    dir_stamp: Option<DirStamp>,
+    runner: Rc<Runner>,
+    /// Plugins loaded from the plugins directory by ID, for handlers run
+    /// from Lua
+    statuses: Rc<RefCell<HashMap<String, Rc<PluginStatus>>>>,
+    storage: Rc<RefCell<StorageRegistry>>,
+    #[cfg(feature = "wasm")]
+    wasm_limits: wasm::WasmLimits,
+}
+
+/// Error tracking for a plugin, shared with handlers run from Lua.
+pub struct PluginStatus {
+    name: String,
+    /// Number of handler calls in a row that have failed
+    failures: Cell<u32>,
+    disabled: Cell<bool>,
+}
+
+impl PluginStatus {
+    fn new(name: &str) -> Rc<Self> {
+        Rc::new(Self { name: name.to_owned(), failures: Cell::new(0), disabled: Cell::new(false) })
+    }
+}
+
+/// A plugin whose metadata has been read, waiting to be loaded in
+/// dependency order.
+enum Pending<'lua> {
+    /// A Lua plugin with a metadata file, which hasn't run yet
+    Lua(PathBuf),
+    /// A Lua plugin without one, which has already run to return its
+    /// plugin table
+    Loaded(Box<LuaPlugin<'lua>>),
+    /// A WebAssembly plugin, which hasn't been instantiated yet
+    #[cfg(feature = "wasm")]
+    Wasm(PathBuf),
+}
+
+pub struct LoadedPlugin<'lua> {
+    plugin: RefCell<Box<dyn Plugin + 'lua>>,
+    status: Rc<PluginStatus>,
+    native: bool,
+}
+
+impl <'lua> LoadedPlugin<'lua> {
+    pub fn plugin(&self) -> impl Deref<Target = Box<dyn Plugin + 'lua>> + '_ {
+        self.plugin.borrow()
+    }
+
+    pub fn disabled(&self) -> bool {
+        self.status.disabled.get()
+    }
+}
+
+/// Runs plugin handlers with a time limit, disabling plugins that fail too
+/// many times in a row.
+struct Runner {
+    deadline: Cell<Option<Instant>>,
+    time_limit: Duration,
+    max_failures: u32,
+}
+
+impl Runner {
+    fn over_budget(&self) -> bool {
+        self.deadline.get().is_some_and(|deadline| Instant::now() > deadline)
+    }
+
+    /// Call a handler, logging any error. Plugins that fail too many times
+    /// in a row are disabled until the next reload.
+    fn run(&self, status: &PluginStatus, handler: impl FnOnce() -> HandlerResult) {
+        if status.disabled.get() {
+            return
+        }
+        // Handlers can be nested (e.g. an event fired from inside another
+        // handler); only the outermost call sets the deadline.
+        let outermost = self.deadline.get().is_none();
+        if outermost {
+            self.deadline.set(Some(Instant::now() + self.time_limit));
+        }
+        let result = catch_unwind(AssertUnwindSafe(handler))
+            .unwrap_or_else(|_| Err("handler panicked".into()));
+        if outermost {
+            self.deadline.set(None);
+        }
+        match result {
+            Ok(()) => status.failures.set(0),
+            Err(e) => {
+                warn!("Error in plugin {}: {}", status.name, e);
+                status.failures.set(status.failures.get() + 1);
+                if self.max_failures > 0 && status.failures.get() >= self.max_failures {
+                    error!("Disabling plugin {} after {} consecutive errors", status.name, status.failures.get());
+                    status.disabled.set(true);
+                }
+            }
+        }
+    }
+}
+
+/// The newest modification time and number of plugin files under a
+/// directory, used to notice when plugins have been changed on disk. Plugin
+/// files are Lua and WebAssembly code, plugin `config.json` files and
+/// plugin metadata files.
+#[derive(PartialEq, Eq, Clone, Copy)]
+struct DirStamp {
+    modified: SystemTime,
+    files: usize,
+}
+
+impl DirStamp {
+    fn of(path: &Path) -> std::io::Result<Self> {
+        let mut stamp = DirStamp { modified: SystemTime::UNIX_EPOCH, files: 0 };
+        stamp.visit(path)?;
+        Ok(stamp)
+    }
+
+    fn visit(&mut self, path: &Path) -> std::io::Result<()> {
+        for entry in read_dir(path)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if meta.is_dir() {
+                self.visit(&entry.path())?;
+            } else if entry.path().extension().is_some_and(|ext| ext == "lua" || ext == "json" || cfg!(feature = "wasm") && ext == "wasm") {
+                self.files += 1;
+                self.modified = self.modified.max(meta.modified()?);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl <'lua> Plugins<'lua> {
-    pub fn new(lua: &'lua Lua) -> Result<Self, mlua::Error> {
-        init_lua::init(lua)?;
+    pub fn new(lua: &'lua Lua, config: &Config) -> Result<Self, mlua::Error> {
+        let players = PlayerRegistry::shared();
+        let worlds = WorldRegistry::shared();
+        init_lua::init(lua, players.clone(), worlds.clone())?;
+
+        // Lua runs on the main thread, so a handler that never returns would
+        // stall the whole server. Check the clock every so often and abort
+        // handlers that have gone over their time limit.
+        let runner = Rc::new(Runner {
+            deadline: Cell::new(None),
+            time_limit: Duration::from_millis(config.plugin_time_limit),
+            max_failures: config.plugin_max_failures,
+        });
+        let runner_2 = runner.clone();
+        lua.set_hook(HookTriggers::every_nth_instruction(1000), move |_, _| {
+            if runner_2.over_budget() {
+                Err(mlua::Error::RuntimeError(
+                    format!("handler exceeded the time limit of {}ms", runner_2.time_limit.as_millis())
+                ))
+            } else {
+                Ok(())
+            }
+        })?;
+        let qc: Table = lua.globals().get("_qc")?;
+        let runner_2 = runner.clone();
+        qc.set("overBudget", lua.create_function(move |_, ()| Ok(runner_2.over_budget()))?)?;
+
+        // Listeners for custom events are called through here so they get
+        // the same time limit and error handling as the server's own events.
+        let statuses: Rc<RefCell<HashMap<String, Rc<PluginStatus>>>> = Rc::new(RefCell::new(HashMap::new()));
+        let runner_2 = runner.clone();
+        let statuses_2 = statuses.clone();
+        let dispatch = lua.create_function(move |_, (id, func, data): (String, Function, Value)| {
+            let status = statuses_2.borrow().get(&id).cloned();
+            match status {
+                Some(status) if !status.disabled.get() => {
+                    runner_2.run(&status, || Ok(func.call::<_, ()>(data)?));
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        })?;
+        qc.set("dispatch", dispatch)?;
+
+        let storage = Rc::new(RefCell::new(StorageRegistry::new(&config.data_dir)));
+        let storage_2 = storage.clone();
+        // Only reachable through each plugin's own `server.storage`, which
+        // passes the ID of the plugin it was created for.
+        let open_storage = lua.create_function(move |_, id: String| {
+            let storage = storage_2.borrow_mut().open(&id).map_err(mlua::Error::RuntimeError)?;
+            Ok(LuaStorage(storage))
+        })?;
+        qc.set("openStorage", open_storage)?;
+
         Ok(Self { 
             lua, 
             plugins: Vec::new(),
+            ctx: Context::new(players.clone(), worlds),
+            dir: config.plugin_dir.clone(),
             cmd_owners: HashMap::new(),
+            players,
+            dir_stamp: None,
+            runner,
+            statuses,
+            storage,
+            #[cfg(feature = "wasm")]
+            wasm_limits: wasm::WasmLimits {
+                fuel: config.wasm_fuel,
+                memory: config.wasm_memory_limit,
+            },
         })
     }
 
+
+    /// Run one of a plugin's handlers through the runner.
+    fn call(&self, pl: &LoadedPlugin<'lua>, handler: impl FnOnce(&mut (dyn Plugin + 'lua), &Context) -> HandlerResult) {
+        self.runner.run(&pl.status, || {
+            let mut plugin = pl.plugin.try_borrow_mut()
+                .map_err(|_| "plugin handler called from inside itself")?;
+            handler(plugin.as_mut(), &self.ctx)
+        })
+    }
+
+    pub fn players(&self) -> SharedPlayers {
+        self.players.clone()
+    }
+
+    pub fn worlds(&self) -> SharedWorlds {
+        self.ctx.worlds().clone()
+    }
+
+    /// Add a plugin written in Rust. Native plugins run before Lua plugins,
+    /// in the order they were registered, and are kept when Lua plugins are
+    /// reloaded. They should be registered before plugins are initialized.
+    pub fn register_native(&mut self, plugin: impl Plugin + 'lua) {
+        self.register_boxed(Box::new(plugin))
+    }
+
+    pub(crate) fn register_boxed(&mut self, plugin: Box<dyn Plugin + 'lua>) {
+        if let Some(other) = self.plugins.iter().find(|other| other.plugin().id() == plugin.id()) {
+            error!("Not registering plugin '{}': another plugin ({}) already has that ID", plugin.id(), other.plugin().name());
+            return
+        }
+        info!("Registered native plugin '{}' version {}", plugin.id(), plugin.version());
+        let natives = self.plugins.iter().filter(|pl| pl.native).count();
+        self.plugins.insert(natives, LoadedPlugin {
+            status: PluginStatus::new(plugin.name()),
+            plugin: RefCell::new(plugin),
+            native: true,
+        });
+    }
+
     pub fn load_plugins(&mut self) {
-        let files = read_dir("plugins").expect("couldn't read plugins directory");
+        self.load_plugin_files();
+        // Taken after loading, since loading writes missing config files
+        self.dir_stamp = DirStamp::of(&self.dir).ok();
+    }
+
+    fn load_plugin_files(&mut self) {
+        let mut files: Vec<_> = match read_dir(&self.dir) {
+            Ok(files) => files.filter_map(Result::ok).collect(),
+            Err(e) => return error!("Couldn't read plugins directory {}: {}", self.dir.display(), e),
+        };
+        files.sort_by_key(|file| file.file_name());
+
+        // IDs of plugins loaded so far, starting with native plugins
+        let mut available: BTreeSet<String> = self.plugins.iter()
+            .filter(|pl| pl.native)
+            .map(|pl| pl.plugin().id().to_owned())
+            .collect();
+        // Metadata is checked before plugin code runs, so plugins that won't
+        // be loaded never get to run their top level. Lua plugins without a
+        // metadata file are the exception: they have to run to return the
+        // plugin table their metadata is in.
+        let mut pending: Vec<(Metadata, Pending<'lua>)> = Vec::new();
         for file in files {
-            let file = file.expect("couldn't read contents of plugins directory");
-            let path = if file.file_type().expect("couldn't get type of plugin file").is_dir() {
+            let (path, default_id) = if file.file_type().expect("couldn't get type of plugin file").is_dir() {
                 let mut main = file.path();
                 main.push("main.lua");
-                main
+                // directories without a main.lua hold libraries or data, not plugins
+                if !main.is_file() {
+                    continue
+                }
+                (main, file.file_name().to_string_lossy().into_owned())
+            } else if file.path().extension().is_some_and(|ext| ext == "lua" || cfg!(feature = "wasm") && ext == "wasm") {
+                (file.path(), file.path().file_stem().unwrap_or_default().to_string_lossy().into_owned())
             } else {
-                file.path()
+                continue
+            };
+            let file_name = file.file_name().to_string_lossy().into_owned();
+            let wasm = path.extension().is_some_and(|ext| ext == "wasm");
+            let file_meta = Metadata::read(&path.with_extension("json")).and_then(|meta| match meta {
+                Some(meta) => meta.with_defaults(&default_id).map(Some),
+                // WebAssembly plugins have nowhere else to declare metadata
+                None if wasm => Metadata::default().with_defaults(&default_id).map(Some),
+                None => Ok(None),
+            });
+            let (meta, pl) = match file_meta {
+                #[cfg(feature = "wasm")]
+                Ok(Some(meta)) if wasm => (meta, Pending::Wasm(path)),
+                Ok(Some(meta)) => (meta, Pending::Lua(path)),
+                Ok(None) => match self.load_lua(&path, &default_id, None) {
+                    Some(pl) => (pl.meta.clone(), Pending::Loaded(Box::new(pl))),
+                    None => continue,
+                },
+                Err(e) => {
+                    error!("Error loading plugin '{}': {}", file_name, e);
+                    continue
+                }
             };
-            let pl = Plugin::load(&path, self.lua).expect("error loading plugin");
-            self.plugins.push(pl);
-            info!("Loaded plugin '{}'", file.file_name().to_string_lossy());
+            if let Some((other, _)) = pending.iter().find(|(other, _)| other.id == meta.id) {
+                error!("Not loading plugin '{}': another plugin ({}) already has the ID '{}'", file_name, other.name, meta.id);
+                continue
+            }
+            if available.contains(&meta.id) {
+                error!("Not loading plugin '{}': another plugin already has the ID '{}'", file_name, meta.id);
+                continue
+            }
+            if let Some(required) = &meta.api_version {
+                match metadata::api_compatible(required, VERSION) {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        error!("Not loading plugin '{}': it requires API version {}, but this is Quectocraft {}", meta.id, required, VERSION);
+                        continue
+                    }
+                    Err(e) => {
+                        error!("Not loading plugin '{}': {}", meta.id, e);
+                        continue
+                    }
+                }
+            }
+            pending.push((meta, pl));
         }
-    }
 
-    pub fn get_responses(&self) -> Vec<Response> {
-        match self.get_responses_inner() {
-            Ok(x) => x,
-            Err(e) => {
-                warn!("Error getting responses: {}", e);
-                Vec::new()
+        for (meta, pl) in load_order::sort_plugins(pending, &available) {
+            // a dependency may have failed to load after the plugins were sorted
+            if let Some(dep) = meta.depends.iter().find(|dep| !available.contains(*dep)) {
+                error!("Not loading plugin '{}': missing dependency '{}'", meta.id, dep);
+                continue
             }
+            let id = meta.id.clone();
+            let plugin: Box<dyn Plugin + 'lua> = match pl {
+                Pending::Lua(path) => match self.load_lua(&path, &id, Some(meta)) {
+                    Some(pl) => self.accept_lua(pl),
+                    None => continue,
+                },
+                Pending::Loaded(pl) => self.accept_lua(*pl),
+                #[cfg(feature = "wasm")]
+                Pending::Wasm(path) => match wasm::WasmPlugin::load(&path, meta, self.players.clone(), self.worlds(), self.storage.clone(), self.wasm_limits) {
+                    Ok(pl) => Box::new(pl),
+                    Err(e) => {
+                        error!("Error loading plugin '{}': {}", id, e);
+                        continue
+                    }
+                },
+            };
+            info!("Loaded plugin '{}' version {}", plugin.id(), plugin.version());
+            let status = PluginStatus::new(plugin.name());
+            self.statuses.borrow_mut().insert(id.clone(), status.clone());
+            available.insert(id);
+            self.plugins.push(LoadedPlugin {
+                plugin: RefCell::new(plugin),
+                status,
+                native: false,
+            });
+        }
+    }
+
+    /// Mark a Lua plugin as loaded, so that its event listeners and services
+    /// are used and its handlers run from Lua are tracked.
+    fn accept_lua(&self, pl: LuaPlugin<'lua>) -> Box<dyn Plugin + 'lua> {
+        if let Err(e) = pl.owner.set("id", pl.meta.id.clone()) {
+            warn!("Error registering plugin {}: {}", pl.meta.id, e);
         }
+        Box::new(pl)
     }
 
-    fn get_responses_inner(&self) -> Result<Vec<Response>, Box<dyn std::error::Error>> {
-        let qc: Table = self.lua.globals().get("_qc")?;
-        let responses: Vec<Response> = self.lua.from_value(qc.get("responses")?)?;
-        qc.set("responses", self.lua.create_table()?)?;
-        Ok(responses)
+    /// Run a Lua plugin's code, with the same time limit as a handler.
+    fn load_lua(&self, path: &Path, default_id: &str, meta: Option<Metadata>) -> Option<LuaPlugin<'lua>> {
+        self.runner.deadline.set(Some(Instant::now() + self.runner.time_limit));
+        let result = LuaPlugin::load(path, default_id, meta, &self.dir, self.lua);
+        self.runner.deadline.set(None);
+        result.map_err(|e| error!("Error loading plugin '{}': {}", default_id, e)).ok()
+    }
+
+    pub fn loaded(&self) -> &[LoadedPlugin<'lua>] {
+        &self.plugins
+    }
+
+    /// Drop every Lua plugin, then load and initialize them again from the
+    /// plugins directory. Native plugins are kept, but errors they have made
+    /// are forgotten. Commands must be registered again afterwards. Plugin
+    /// storage is saved first and read from disk again when reopened.
+    pub fn reload(&mut self) {
+        self.storage.borrow_mut().close_all();
+        self.plugins.retain(|pl| pl.native);
+        for pl in &self.plugins {
+            pl.status.failures.set(0);
+            pl.status.disabled.set(false);
+        }
+        self.cmd_owners.clear();
+        self.statuses.borrow_mut().clear();
+        if let Err(e) = self.lua.globals().get::<_, Table>("_qc").and_then(|qc| qc.get::<_, Function>("reset")?.call::<_, ()>(())) {
+            warn!("Error resetting plugin events and services: {}", e);
+        }
+        self.load_plugins();
+        for pl in self.plugins.iter().filter(|pl| !pl.native) {
+            self.call(pl, |p, ctx| p.init(ctx));
+        }
+    }
+
+    /// Write any unsaved plugin storage to disk.
+    pub fn save(&self) {
+        self.storage.borrow().flush_all();
+    }
+
+    /// Whether anything in the plugins directory has changed since the
+    /// plugins were last loaded.
+    pub fn changed_on_disk(&self) -> bool {
+        match DirStamp::of(&self.dir) {
+            Ok(stamp) => self.dir_stamp != Some(stamp),
+            Err(_) => false,
+        }
     }
 
     pub fn init(&self) {
         for pl in &self.plugins {
-            if let Some(init) = &pl.event_handlers.init {
-                if let Err(e) = init.call::<_, ()>(()) {
-                    warn!("Error in plugin {}: {}", pl.name, e);
-                }
-            }
+            self.call(pl, |p, ctx| p.init(ctx));
         }
     }
 
-    pub fn register_commands(&mut self, commands: Commands) -> Result<Commands, mlua::Error> {
+    pub fn register_commands(&mut self, commands: Commands) -> Commands {
         let commands = Rc::new(RefCell::new(commands));
         let cmd_owners = Rc::new(RefCell::new(HashMap::new()));
         for (i, pl) in self.plugins.iter().enumerate() {
-            let commands_2 = commands.clone();
-            let cmd_owners_2 = cmd_owners.clone();
-            let pl_id = pl.id.clone();
-            let add_command = self.lua.create_function(move |_, name: String| {
-                let scoped_name = format!("{}:{}", pl_id, name);
-                let mut cmds = commands_2.borrow_mut();
-                let id1 = cmds.create_simple_cmd(&name);
-                let id2 = cmds.create_simple_cmd(&scoped_name);
-                if id1.is_none() || id2.is_none() { 
-                    return Ok(mlua::Nil)
-                }
-                cmd_owners_2.borrow_mut().insert(name, i);
-                cmd_owners_2.borrow_mut().insert(scoped_name, i);
-                Ok(mlua::Nil)
-            })?;
-            let registry = self.lua.create_table()?;
-            registry.set("addCommand", add_command)?;
-            if let Some(init) = &pl.event_handlers.register_commands {
-                if let Err(e) = init.call::<_, ()>((registry.clone(),)) {
-                    warn!("Error in plugin {}: {}", pl.name, e);
-                }
-            }
+            let registry = CommandRegistry::new(pl.plugin().id(), i, commands.clone(), cmd_owners.clone());
+            self.call(pl, |p, _| p.register_commands(&registry));
         }
-        let cb = commands.borrow();
-        self.cmd_owners = (*cmd_owners.borrow()).clone();
-        Ok((*cb).clone())
+        self.cmd_owners = cmd_owners.take();
+        let commands = commands.borrow().clone();
+        commands
     }
 
-    pub fn player_join(&self, player: &Player) {
+    /// Run the `preLogin` handlers, which can change what the player joins
+    /// with.
+    pub fn pre_login(&self, player: &Player, settings: &mut JoinSettings) {
+        for pl in &self.plugins {
+            self.call(pl, |p, ctx| p.pre_login(ctx, player, settings));
+        }
+    }
+
+    /// Run the `playerJoin` handlers. `replay` is true when the player was
+    /// already online and the event is being repeated after a reload, which
+    /// only Lua plugins see.
+    pub fn player_join(&self, player: &Player, replay: bool) {
         if let Err(e) = self.add_player(player) {
-            warn!("Error adding player: {}", e);
-            return
+            return warn!("Error adding player: {}", e);
         }
-        for pl in &self.plugins {
-            if let Some(init) = &pl.event_handlers.player_join {
-                if let Err(e) = init.call::<_, ()>((player.name.as_str(), player.uuid.to_string())) {
-                    warn!("Error in plugin {}: {}", pl.name, e);
-                }
-            }
+        for pl in self.plugins.iter().filter(|pl| !(replay && pl.native)) {
+            self.call(pl, |p, ctx| p.player_join(ctx, player, replay));
         }
     }
 
+    /// Add a player to `server.players`
     fn add_player(&self, player: &Player) -> Result<(), mlua::Error> {
         let server: Table = self.lua.globals().get("server")?;
         let players: Table = server.get("players")?;
-        players.set(player.uuid.to_string(), player.name.as_str())?;
-        Ok(())
+        let lua_player = self.lua.create_userdata(LuaPlayer::new(player, &self.ctx))?;
+        players.set(player.uuid.to_string(), lua_player)
     }
 
     pub fn player_leave(&self, player: &Player) {
-        if let Err(e) = self.remove_player(player.uuid) {
-            warn!("Error removing player: {}", e);
-            return
-        }
         for pl in &self.plugins {
-            if let Some(func) = &pl.event_handlers.player_leave {
-                if let Err(e) = func.call::<_, ()>((player.name.as_str(), player.uuid.to_string())) {
-                    warn!("Error in plugin {}: {}", pl.name, e);
-                }
-            }
+            self.call(pl, |p, ctx| p.player_leave(ctx, player));
+        }
+        if let Err(e) = self.remove_player(player) {
+            warn!("Error removing player: {}", e);
         }
     }
 
-    fn remove_player(&self, uuid: Uuid) -> Result<(), mlua::Error> {
+    fn remove_player(&self, player: &Player) -> Result<(), mlua::Error> {
         let server: Table = self.lua.globals().get("server")?;
         let players: Table = server.get("players")?;
-        players.set(uuid.to_string(), mlua::Nil)?;
-        Ok(())
+        players.set(player.uuid.to_string(), mlua::Nil)
     }
     
     pub fn chat_message(&self, player: &Player, message: &str) {
         for pl in &self.plugins {
-            if let Some(func) = &pl.event_handlers.chat_message {
-                if let Err(e) = func.call::<_, ()>((message, player.name.as_str(), player.uuid.to_string())) {
-                    warn!("Error in plugin {}: {}", pl.name, e);
-                }
-            }
+            self.call(pl, |p, ctx| p.chat_message(ctx, player, message));
         }
     }
 
     pub fn command(&self, player: &Player, command: &str, args: &str) {
         if let Some(owner) = self.cmd_owners.get(command) {
             let pl = &self.plugins[*owner];
-            if let Some(func) = &pl.event_handlers.command {
-                if let Err(e) = func.call::<_, ()>((command, args, player.name.as_str(), player.uuid.to_string())) {
-                    warn!("Error in plugin {}: {}", pl.name, e);
-                }
-            } else {
-                warn!("Plugin {} registered a command but no command handler was found", pl.id);
-            }
+            self.call(pl, |p, ctx| p.command(ctx, player, command, args));
         }
     }
 
     pub fn plugin_message(&self, player: &Player, channel: &str, data: &[u8]) {
         for pl in &self.plugins {
-            if let Some(func) = &pl.event_handlers.plugin_message {
-                if let Err(e) = func.call::<_, ()>((channel, data, player.name.as_str(), player.uuid.to_string())) {
-                    warn!("Error in plugin {}: {}", pl.name, e);
-                }
-            }
+            self.call(pl, |p, ctx| p.plugin_message(ctx, player, channel, data));
+        }
+    }
+
+    pub fn block_interact(&self, player: &Player, event: &mut BlockEvent) {
+        for pl in &self.plugins {
+            self.call(pl, |p, ctx| p.block_interact(ctx, player, event));
+        }
+    }
+
+    pub fn block_break(&self, player: &Player, event: &mut BlockEvent) {
+        for pl in &self.plugins {
+            self.call(pl, |p, ctx| p.block_break(ctx, player, event));
+        }
+    }
+
+    pub fn block_place(&self, player: &Player, event: &mut BlockEvent) {
+        for pl in &self.plugins {
+            self.call(pl, |p, ctx| p.block_place(ctx, player, event));
+        }
+    }
+
+    pub fn tick(&self) {
+        for pl in &self.plugins {
+            self.call(pl, |p, ctx| p.tick(ctx));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn storage_is_per_plugin() {
+        let dir = std::env::temp_dir().join(format!("qc-storage-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let plugin_dir = dir.join("plugins");
+        fs::create_dir_all(&plugin_dir).unwrap();
+        fs::write(plugin_dir.join("a.lua"), r#"
+            server.storage():set("secret", "hidden")
+            return {}
+        "#).unwrap();
+        // b tries every way of naming a's storage
+        fs::write(plugin_dir.join("b.lua"), r#"
+            local plugin = {}
+            function plugin.init()
+                local seen = {}
+                for _, other in ipairs({ server.storage("a"), server.storage({ id = "a" }), server.storage() }) do
+                    table.insert(seen, tostring(other:get("secret")))
+                end
+                server.storage():set("seen", seen)
+            end
+            return plugin
+        "#).unwrap();
+
+        let config = Config { plugin_dir, data_dir: dir.join("data"), ..Config::default() };
+        let lua = Lua::new();
+        let mut plugins = Plugins::new(&lua, &config).unwrap();
+        plugins.load_plugins();
+        plugins.init();
+        assert_eq!(plugins.loaded().len(), 2);
+
+        let mut storage = plugins.storage.borrow_mut();
+        assert_eq!(storage.open("a").unwrap().borrow().get("secret"), Some(&json!("hidden")));
+        assert_eq!(storage.open("b").unwrap().borrow().get("secret"), None);
+        assert_eq!(storage.open("b").unwrap().borrow().get("seen"), Some(&json!(["nil", "nil", "nil"])));
+        drop(storage);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn metadata_from_table_or_file() {
+        let dir = std::env::temp_dir().join(format!("qc-metadata-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let plugin_dir = dir.join("plugins");
+        fs::create_dir_all(&plugin_dir).unwrap();
+        fs::write(plugin_dir.join("bar.lua"), r#"
+            return { id = "foo", description = "from the table", depends = { "baz" } }
+        "#).unwrap();
+        fs::write(plugin_dir.join("baz.lua"), r#"
+            return { id = "ignored", name = "Table name", description = "from the table" }
+        "#).unwrap();
+        fs::write(plugin_dir.join("baz.json"), r#"{ "name": "Baz" }"#).unwrap();
+
+        let config = Config { plugin_dir, data_dir: dir.join("data"), ..Config::default() };
+        let lua = Lua::new();
+        let mut plugins = Plugins::new(&lua, &config).unwrap();
+        plugins.load_plugins();
+        let loaded: Vec<_> = plugins.loaded().iter()
+            .map(|pl| (pl.plugin().id().to_owned(), pl.plugin().name().to_owned(), pl.plugin().description().map(str::to_owned)))
+            .collect();
+        assert_eq!(loaded, [
+            ("baz".to_owned(), "Baz".to_owned(), None),
+            ("foo".to_owned(), "foo".to_owned(), Some("from the table".to_owned())),
+        ]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn capabilities_from_table() {
+        let dir = std::env::temp_dir().join(format!("qc-capabilities-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let plugin_dir = dir.join("plugins");
+        fs::create_dir_all(&plugin_dir).unwrap();
+        fs::write(plugin_dir.join("caps.lua"), r#"
+            local top_level = rawset ~= nil
+            local plugin = { capabilities = { "metatables" } }
+            function plugin.init()
+                server.storage():set("granted", { top_level, rawset ~= nil })
+            end
+            return plugin
+        "#).unwrap();
+
+        let config = Config { plugin_dir, data_dir: dir.join("data"), ..Config::default() };
+        let lua = Lua::new();
+        let mut plugins = Plugins::new(&lua, &config).unwrap();
+        plugins.load_plugins();
+        plugins.init();
+        assert_eq!(plugins.loaded()[0].plugin().capabilities(), ["metatables"]);
+        let granted = plugins.storage.borrow_mut().open("caps").unwrap().borrow().get("granted").cloned();
+        assert_eq!(granted, Some(json!([false, true])));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn require_stays_in_plugin() {
+        let dir = std::env::temp_dir().join(format!("qc-require-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let plugin_dir = dir.join("plugins");
+        for sub in ["a", "b", "lib"] {
+            fs::create_dir_all(plugin_dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join("x.lua"), "return 'outside'").unwrap();
+        fs::write(plugin_dir.join("lib/shared.lua"), "return {}").unwrap();
+        fs::write(plugin_dir.join("b/secret.lua"), "return 'b'").unwrap();
+        fs::write(plugin_dir.join("b/main.lua"), r#"
+            local plugin = {}
+            function plugin.init()
+                server.storage():set("shared", tostring(require("shared")))
+                server.storage():set("injected", (pcall(require, "injected")))
+            end
+            return plugin
+        "#).unwrap();
+        let outside = dir.join("x").to_string_lossy().into_owned();
+        fs::write(plugin_dir.join("a/helper.lua"), "return 'a'").unwrap();
+        fs::write(plugin_dir.join("a/main.lua"), format!(r#"
+            package.loaded.injected = "from a"
+            local plugin = {{}}
+            function plugin.init()
+                local results = {{}}
+                for _, name in ipairs({{ "../x", "../../x", "{}", "/etc/passwd", "b.secret", "../b/secret", "secret" }}) do
+                    local ok, err = pcall(require, name)
+                    results[name] = ok and "loaded" or tostring(err)
+                end
+                results.helper = require("helper")
+                results.shared = tostring(require("shared"))
+                server.storage():set("results", results)
+            end
+            return plugin
+        "#, outside)).unwrap();
+
+        let config = Config { plugin_dir, data_dir: dir.join("data"), ..Config::default() };
+        let lua = Lua::new();
+        let mut plugins = Plugins::new(&lua, &config).unwrap();
+        plugins.load_plugins();
+        plugins.init();
+        let mut storage = plugins.storage.borrow_mut();
+        let a = storage.open("a").unwrap();
+        let results = a.borrow().get("results").cloned().unwrap();
+        for name in ["../x", "../../x", outside.as_str(), "/etc/passwd", "../b/secret"] {
+            assert!(results[name].as_str().unwrap().contains("invalid module name"), "{}: {}", name, results[name]);
+        }
+        // another plugin's modules aren't on the search path
+        for name in ["b.secret", "secret"] {
+            assert!(results[name].as_str().unwrap().contains("not found"), "{}: {}", name, results[name]);
         }
+        assert_eq!(results["helper"], json!("a"));
+        // each plugin gets its own copy of a shared module
+        let b = storage.open("b").unwrap();
+        assert_ne!(Some(&results["shared"]), b.borrow().get("shared"));
+        assert_eq!(b.borrow().get("injected"), Some(&json!(false)));
+        drop(storage);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn wasm_ids_are_checked_with_lua() {
+        let dir = std::env::temp_dir().join(format!("qc-wasm-ids-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let plugin_dir = dir.join("plugins");
+        fs::create_dir_all(&plugin_dir).unwrap();
+        let empty_module = b"\0asm\x01\0\0\0";
+        fs::write(plugin_dir.join("foo.lua"), "return {}").unwrap();
+        fs::write(plugin_dir.join("foo.wasm"), empty_module).unwrap();
+        fs::write(plugin_dir.join("a.wasm"), empty_module).unwrap();
+        fs::write(plugin_dir.join("a.json"), r#"{ "id": "b", "depends": ["foo"] }"#).unwrap();
+        fs::write(plugin_dir.join("b.lua"), "return {}").unwrap();
+
+        let config = Config { plugin_dir, data_dir: dir.join("data"), ..Config::default() };
+        let lua = Lua::new();
+        let mut plugins = Plugins::new(&lua, &config).unwrap();
+        plugins.load_plugins();
+        let loaded: Vec<_> = plugins.loaded().iter().map(|pl| pl.plugin().id().to_owned()).collect();
+        // a.wasm claims b before b.lua, and foo.lua claims foo before foo.wasm
+        assert_eq!(loaded, ["foo", "b"]);
+        let _ = fs::remove_dir_all(&dir);
     }
 }
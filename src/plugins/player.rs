@@ -0,0 +1,153 @@
+use mlua::{UserData, UserDataFields, UserDataMethods, Lua, Value, LuaSerdeExt, MetaMethod, AnyUserData, Table};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{config::GameMode, network::{Player, PlayerInfo, SharedPlayers}, world::SharedWorlds};
+
+use super::Context;
+
+/// World name, then optionally x, y, z, yaw and pitch.
+type SetWorldArgs = (String, Option<f64>, Option<f64>, Option<f64>, Option<f32>, Option<f32>);
+
+/// A player as seen from Lua. Name and UUID never change, everything else
+/// is looked up in the player registry so it stays current.
+#[derive(Clone)]
+pub struct LuaPlayer {
+    name: String,
+    uuid: Uuid,
+    players: SharedPlayers,
+    worlds: SharedWorlds,
+}
+
+impl LuaPlayer {
+    pub fn new(player: &Player, ctx: &Context) -> Self {
+        Self {
+            name: player.name.clone(),
+            uuid: player.uuid,
+            players: ctx.players().clone(),
+            worlds: ctx.worlds().clone(),
+        }
+    }
+}
+
+/// Get the Lua object for a player, reusing the one in `server.players` so
+/// plugins can compare and store player objects.
+pub fn lua_player<'lua>(lua: &'lua Lua, player: &Player, ctx: &Context) -> mlua::Result<AnyUserData<'lua>> {
+    let server: Table = lua.globals().get("server")?;
+    let online: Table = server.get("players")?;
+    match online.get(player.uuid.to_string())? {
+        Some(lua_player) => Ok(lua_player),
+        None => lua.create_userdata(LuaPlayer::new(player, ctx)),
+    }
+}
+
+pub fn to_chat(lua: &Lua, message: Value, default: Option<serde_json::Value>) -> mlua::Result<serde_json::Value> {
+    match (message, default) {
+        (Value::Nil, Some(default)) => Ok(default),
+        (Value::String(s), _) => Ok(json!({ "text": s.to_str()? })),
+        (table @ Value::Table(_), _) => lua.from_value(table),
+        _ => Err(mlua::Error::RuntimeError("message must be a string or table".to_owned())),
+    }
+}
+
+pub fn default_kick_reason() -> serde_json::Value {
+    json!({ "translate": "multiplayer.disconnect.generic" })
+}
+
+/// Accept either a player object or a name/UUID string wherever a player is expected.
+pub fn player_key(player: Value) -> mlua::Result<String> {
+    match player {
+        Value::String(s) => Ok(s.to_str()?.to_owned()),
+        Value::UserData(ud) => Ok(ud.borrow::<LuaPlayer>()?.uuid.to_string()),
+        _ => Err(mlua::Error::RuntimeError("player must be a player or a string".to_owned())),
+    }
+}
+
+/// Run an action against an online player immediately. Returns `true`, or
+/// `false` and an error message, so plugins can check the result Lua-style.
+pub fn with_player<F>(players: &SharedPlayers, key: &str, action: F) -> (bool, Option<String>)
+where F: FnOnce(&mut PlayerInfo) -> std::io::Result<()> {
+    let mut players = players.borrow_mut();
+    let Some(player) = players.find_mut(key) else {
+        return (false, Some(format!("player {} is not online", key)))
+    };
+    match action(player) {
+        Ok(()) => (true, None),
+        Err(e) => (false, Some(e.to_string())),
+    }
+}
+
+impl UserData for LuaPlayer {
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("name", |_, this| Ok(this.name.clone()));
+        fields.add_field_method_get("uuid", |_, this| Ok(this.uuid.to_string()));
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("isOnline", |_, this, ()| {
+            Ok(this.players.borrow().get(&this.uuid).is_some())
+        });
+        methods.add_method("getAddress", |_, this, ()| {
+            let players = this.players.borrow();
+            let addr = players.get(&this.uuid).map(|p| p.addr);
+            Ok((addr.map(|a| a.ip().to_string()), addr.map(|a| a.port())))
+        });
+        methods.add_method("getPing", |_, this, ()| {
+            let players = this.players.borrow();
+            Ok(players.get(&this.uuid).and_then(|p| p.ping).map(|d| d.as_millis() as u64))
+        });
+        methods.add_method("getLocale", |_, this, ()| {
+            let players = this.players.borrow();
+            Ok(players.get(&this.uuid).and_then(|p| p.locale.clone()))
+        });
+        methods.add_method("getGamemode", |_, this, ()| {
+            let players = this.players.borrow();
+            Ok(players.get(&this.uuid).map(|p| p.gamemode.name()))
+        });
+        methods.add_method("setGamemode", |_, this, gamemode: String| {
+            let gamemode: GameMode = gamemode.parse().map_err(mlua::Error::RuntimeError)?;
+            Ok(with_player(&this.players, &this.uuid.to_string(), |p| p.set_gamemode(gamemode)))
+        });
+        methods.add_method("sendMessage", |lua, this, message: Value| {
+            let message = to_chat(lua, message, None)?;
+            Ok(with_player(&this.players, &this.uuid.to_string(), |p| p.send_message(message)))
+        });
+        methods.add_method("sendPluginMessage", |_, this, (channel, data): (String, Vec<u8>)| {
+            Ok(with_player(&this.players, &this.uuid.to_string(), |p| p.send_plugin_message(channel, data)))
+        });
+        methods.add_method("kick", |lua, this, reason: Value| {
+            let reason = to_chat(lua, reason, Some(default_kick_reason()))?;
+            Ok(with_player(&this.players, &this.uuid.to_string(), |p| p.disconnect(reason)))
+        });
+        methods.add_method("teleport", |_, this, (x, y, z, yaw, pitch): (f64, f64, f64, Option<f32>, Option<f32>)| {
+            Ok(with_player(&this.players, &this.uuid.to_string(), |p| {
+                p.teleport(x, y, z, yaw.unwrap_or(0.0), pitch.unwrap_or(0.0))
+            }))
+        });
+
+        methods.add_method("getWorld", |_, this, ()| {
+            let players = this.players.borrow();
+            Ok(players.get(&this.uuid).map(|p| p.world.clone()))
+        });
+        methods.add_method("setWorld", |_, this, (name, x, y, z, yaw, pitch): SetWorldArgs| {
+            let mut worlds = this.worlds.borrow_mut();
+            let Some(world) = worlds.get_mut(&name) else {
+                return Ok((false, Some(format!("there is no world named {}", name))))
+            };
+            let position = match (x, y, z) {
+                (Some(x), Some(y), Some(z)) => (x, y, z),
+                _ => {
+                    let (x, y, z) = world.spawn();
+                    (x as f64 + 0.5, y as f64, z as f64 + 0.5)
+                }
+            };
+            let rotation = (yaw.unwrap_or(0.0), pitch.unwrap_or(0.0));
+            Ok(with_player(&this.players, &this.uuid.to_string(), |p| p.change_world(&name, world, position, rotation)))
+        });
+
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| Ok(this.name.clone()));
+        methods.add_meta_method(MetaMethod::Eq, |_, this, other: mlua::AnyUserData| {
+            Ok(other.borrow::<LuaPlayer>().map(|o| o.uuid == this.uuid).unwrap_or(false))
+        });
+    }
+}
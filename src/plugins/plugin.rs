@@ -1,50 +1,93 @@
-use std::path::Path;
-
-use mlua::{Function, Table, Lua};
-
-pub struct EventHandlers<'lua> {
-    pub init: Option<Function<'lua>>,
-    pub register_commands: Option<Function<'lua>>,
-    pub player_join: Option<Function<'lua>>,
-    pub player_leave: Option<Function<'lua>>,
-    pub chat_message: Option<Function<'lua>>,
-    pub command: Option<Function<'lua>>,
-    pub plugin_message: Option<Function<'lua>>,
-}   
-
-pub struct Plugin<'lua> {
-    pub id: String,
-    pub name: String,
-    pub version: String,
-    pub event_handlers: EventHandlers<'lua>,
+use std::{rc::Rc, cell::RefCell, collections::HashMap};
+
+use crate::{network::{BlockEvent, JoinSettings, Player, SharedPlayers}, protocol::command::Commands, world::SharedWorlds};
+
+pub type HandlerResult = Result<(), Box<dyn std::error::Error>>;
+
+/// A server plugin. Lua plugins implement this through `LuaPlugin`; native
+/// plugins implement it directly and are registered with
+/// `Plugins::register_native`. Every handler is optional. Errors returned
+/// from handlers are logged and count towards disabling the plugin, the same
+/// as errors in Lua handlers.
+pub trait Plugin {
+    fn id(&self) -> &str;
+    fn name(&self) -> &str { self.id() }
+    fn version(&self) -> &str { "?" }
+    fn description(&self) -> Option<&str> { None }
+    fn authors(&self) -> &[String] { &[] }
+    fn capabilities(&self) -> &[String] { &[] }
+
+    fn init(&mut self, _ctx: &Context) -> HandlerResult { Ok(()) }
+    fn register_commands(&mut self, _registry: &CommandRegistry) -> HandlerResult { Ok(()) }
+    /// Called before a player joins, while they are not online yet. Changes
+    /// to `settings` apply to this player only.
+    fn pre_login(&mut self, _ctx: &Context, _player: &Player, _settings: &mut JoinSettings) -> HandlerResult { Ok(()) }
+    /// `replay` is true when the player was already online and the event is
+    /// being repeated after a reload.
+    fn player_join(&mut self, _ctx: &Context, _player: &Player, _replay: bool) -> HandlerResult { Ok(()) }
+    fn player_leave(&mut self, _ctx: &Context, _player: &Player) -> HandlerResult { Ok(()) }
+    fn chat_message(&mut self, _ctx: &Context, _player: &Player, _message: &str) -> HandlerResult { Ok(()) }
+    fn command(&mut self, _ctx: &Context, _player: &Player, _command: &str, _args: &str) -> HandlerResult { Ok(()) }
+    fn plugin_message(&mut self, _ctx: &Context, _player: &Player, _channel: &str, _data: &[u8]) -> HandlerResult { Ok(()) }
+    /// Called when a player right clicks a block. Cancelling it also stops
+    /// a block being placed against it.
+    fn block_interact(&mut self, _ctx: &Context, _player: &Player, _event: &mut BlockEvent) -> HandlerResult { Ok(()) }
+    /// Called when a player breaks a block, before it is removed.
+    fn block_break(&mut self, _ctx: &Context, _player: &Player, _event: &mut BlockEvent) -> HandlerResult { Ok(()) }
+    /// Called when a player places a block, before it is set. Changing
+    /// `event.state` places a different block.
+    fn block_place(&mut self, _ctx: &Context, _player: &Player, _event: &mut BlockEvent) -> HandlerResult { Ok(()) }
+    /// Called once every server tick (20 times a second).
+    fn tick(&mut self, _ctx: &Context) -> HandlerResult { Ok(()) }
 }
 
-impl <'lua> Plugin<'lua> {
-    pub fn load(path: &Path, lua: &'lua Lua) -> Result<Self, Box<dyn std::error::Error>> {
-        let chunk = lua.load(path);
-        let module: Table = chunk.eval()?;
-
-        let id: String = module.get("id")?;
-        let name: String = module.get("name").unwrap_or_else(|_| id.clone());
-        let version: String = module.get("version").unwrap_or_else(|_| "?".to_owned());
-
-        let init: Option<Function<'lua>> = module.get("init").ok();
-        let register_commands: Option<Function<'lua>> = module.get("registerCommands").ok();
-        let player_join: Option<Function<'lua>> = module.get("playerJoin").ok();
-        let player_leave: Option<Function<'lua>> = module.get("playerLeave").ok();
-        let chat_message: Option<Function<'lua>> = module.get("chatMessage").ok();
-        let command: Option<Function<'lua>> = module.get("command").ok();
-        let plugin_message: Option<Function<'lua>> = module.get("pluginMessage").ok();
-
-        let event_handlers = EventHandlers { 
-            init, 
-            register_commands, 
-            player_join, 
-            player_leave, 
-            chat_message, 
-            command,
-            plugin_message,
-        };
-        Ok(Plugin { id, name, version, event_handlers })
+/// Access to the server from plugin handlers.
+pub struct Context {
+    players: SharedPlayers,
+    worlds: SharedWorlds,
+}
+
+impl Context {
+    pub fn new(players: SharedPlayers, worlds: SharedWorlds) -> Self {
+        Self { players, worlds }
+    }
+
+    pub fn players(&self) -> &SharedPlayers {
+        &self.players
+    }
+
+    pub fn worlds(&self) -> &SharedWorlds {
+        &self.worlds
+    }
+}
+
+/// Collects the commands registered by one plugin.
+#[derive(Clone)]
+pub struct CommandRegistry {
+    plugin_id: String,
+    index: usize,
+    commands: Rc<RefCell<Commands>>,
+    owners: Rc<RefCell<HashMap<String, usize>>>,
+}
+
+impl CommandRegistry {
+    pub fn new(plugin_id: &str, index: usize, commands: Rc<RefCell<Commands>>, owners: Rc<RefCell<HashMap<String, usize>>>) -> Self {
+        Self { plugin_id: plugin_id.to_owned(), index, commands, owners }
+    }
+
+    /// Add a command, which can also be run as `<plugin id>:<name>`. Returns
+    /// false if the name is already taken.
+    pub fn add_command(&self, name: &str) -> bool {
+        let scoped_name = format!("{}:{}", self.plugin_id, name);
+        let mut cmds = self.commands.borrow_mut();
+        let id1 = cmds.create_simple_cmd(name);
+        let id2 = cmds.create_simple_cmd(&scoped_name);
+        if id1.is_none() || id2.is_none() {
+            return false
+        }
+        let mut owners = self.owners.borrow_mut();
+        owners.insert(name.to_owned(), self.index);
+        owners.insert(scoped_name, self.index);
+        true
     }
 }
@@ -0,0 +1,136 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, rc::Rc, cell::RefCell, fs::{self, File}, io::Write};
+
+use log::warn;
+use mlua::{UserData, UserDataMethods, Value, LuaSerdeExt, SerializeOptions};
+use serde_json::{Map, Value as Json};
+
+use super::metadata::valid_id;
+
+/// A plugin's key-value store, kept in memory and written to
+/// `<dir>/<plugin id>/storage.json` when flushed.
+pub struct Storage {
+    path: PathBuf,
+    data: Map<String, Json>,
+    dirty: bool,
+}
+
+impl Storage {
+    fn open(path: PathBuf) -> Result<Self, String> {
+        let data = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| format!("couldn't parse {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Map::new(),
+            Err(e) => return Err(format!("couldn't read {}: {}", path.display(), e)),
+        };
+        Ok(Self { path, data, dirty: false })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        self.data.get(key)
+    }
+
+    pub fn set(&mut self, key: String, value: Json) {
+        self.data.insert(key, value);
+        self.dirty = true;
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        let removed = self.data.remove(key).is_some();
+        self.dirty |= removed;
+        removed
+    }
+
+    /// Write the data to disk if it has changed. The data is written to a
+    /// temporary file first and then moved into place, so a crash part way
+    /// through never leaves a half-written store behind.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if !self.dirty {
+            return Ok(())
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec_pretty(&self.data)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// The stores that have been opened by plugins, by plugin ID.
+pub struct StorageRegistry {
+    dir: PathBuf,
+    open: HashMap<String, Rc<RefCell<Storage>>>,
+}
+
+impl StorageRegistry {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self { dir: dir.as_ref().to_owned(), open: HashMap::new() }
+    }
+
+    pub fn open(&mut self, id: &str) -> Result<Rc<RefCell<Storage>>, String> {
+        if let Some(storage) = self.open.get(id) {
+            return Ok(storage.clone())
+        }
+        if !valid_id(id) {
+            return Err(format!("invalid plugin ID '{}' for storage", id))
+        }
+        let path = self.dir.join(id).join("storage.json");
+        let storage = Rc::new(RefCell::new(Storage::open(path)?));
+        self.open.insert(id.to_owned(), storage.clone());
+        Ok(storage)
+    }
+
+    /// Write every store with unsaved changes to disk.
+    pub fn flush_all(&self) {
+        for (id, storage) in &self.open {
+            if let Err(e) = storage.borrow_mut().flush() {
+                warn!("Error saving storage for plugin {}: {}", id, e);
+            }
+        }
+    }
+
+    /// Flush and forget every store, so they are read from disk again the
+    /// next time they're opened.
+    pub fn close_all(&mut self) {
+        self.flush_all();
+        self.open.clear();
+    }
+}
+
+pub struct LuaStorage(pub Rc<RefCell<Storage>>);
+
+impl UserData for LuaStorage {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("get", |lua, this, key: String| {
+            match this.0.borrow().get(&key) {
+                Some(value) => lua.to_value_with(value, SerializeOptions::new().serialize_none_to_null(false)),
+                None => Ok(Value::Nil),
+            }
+        });
+        methods.add_method("set", |lua, this, (key, value): (String, Value)| {
+            if let Value::Nil = value {
+                this.0.borrow_mut().remove(&key);
+            } else {
+                let value: Json = lua.from_value(value)?;
+                this.0.borrow_mut().set(key, value);
+            }
+            Ok(())
+        });
+        methods.add_method("remove", |_, this, key: String| {
+            Ok(this.0.borrow_mut().remove(&key))
+        });
+        methods.add_method("keys", |_, this, ()| {
+            Ok(this.0.borrow().data.keys().cloned().collect::<Vec<_>>())
+        });
+        methods.add_method("flush", |_, this, ()| {
+            match this.0.borrow_mut().flush() {
+                Ok(()) => Ok((true, None)),
+                Err(e) => Ok((false, Some(e.to_string()))),
+            }
+        });
+    }
+}
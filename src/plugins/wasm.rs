@@ -0,0 +1,478 @@
+use std::{path::Path, rc::Rc, cell::RefCell, fs};
+
+use log::{trace, debug, info, warn, error};
+use serde_json::json;
+use wasmi::{Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Instance, Caller, Val, Extern, core::TrapCode};
+
+use crate::{config::GameMode, network::{set_block, BlockEvent, JoinSettings, Player, SharedPlayers}, protocol::clientbound::SystemChatMessage, world::SharedWorlds, VERSION};
+
+use super::{metadata::Metadata, plugin::{Plugin, Context, CommandRegistry, HandlerResult}, player::{with_player, default_kick_reason}, storage::StorageRegistry};
+
+/// Resource limits for each WebAssembly plugin.
+#[derive(Clone, Copy)]
+pub struct WasmLimits {
+    /// Fuel available to each handler call, roughly one unit per instruction
+    pub fuel: u64,
+    /// Maximum size of the plugin's linear memory, in bytes
+    pub memory: usize,
+}
+
+struct HostState {
+    id: String,
+    players: SharedPlayers,
+    worlds: SharedWorlds,
+    storage: Rc<RefCell<StorageRegistry>>,
+    /// Set while `register_commands` is running
+    registry: Option<CommandRegistry>,
+    /// Set while `pre_login` is running
+    join: Option<JoinSettings>,
+    limits: StoreLimits,
+}
+
+/// A plugin compiled to WebAssembly. See `docs/plugins.md` for the
+/// functions it can import and export.
+pub struct WasmPlugin {
+    id: String,
+    name: String,
+    version: String,
+    description: Option<String>,
+    authors: Vec<String>,
+    store: Store<HostState>,
+    instance: Instance,
+    fuel: u64,
+}
+
+type HostResult<T> = Result<T, wasmi::Error>;
+
+fn memory(caller: &Caller<HostState>) -> HostResult<wasmi::Memory> {
+    caller.get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("plugin does not export its memory"))
+}
+
+/// Copy bytes out of the plugin's memory. The range is checked against the
+/// memory before anything is allocated, since `len` comes from the plugin.
+fn read_bytes(caller: &Caller<HostState>, ptr: i32, len: i32) -> HostResult<Vec<u8>> {
+    let memory = memory(caller)?;
+    let start = ptr as u32 as usize;
+    let bytes = start.checked_add(len.max(0) as usize)
+        .and_then(|end| memory.data(caller).get(start..end))
+        .ok_or_else(|| wasmi::Error::new("out of bounds memory access"))?;
+    Ok(bytes.to_vec())
+}
+
+fn read_str(caller: &Caller<HostState>, ptr: i32, len: i32) -> HostResult<String> {
+    String::from_utf8(read_bytes(caller, ptr, len)?)
+        .map_err(|_| wasmi::Error::new("string is not valid UTF-8"))
+}
+
+/// Copy `data` into a buffer provided by the plugin if it fits, returning
+/// its length either way so the plugin can retry with a bigger buffer.
+fn write_out(caller: &mut Caller<HostState>, ptr: i32, cap: i32, data: &[u8]) -> HostResult<i32> {
+    if data.len() <= cap.max(0) as usize {
+        let memory = memory(caller)?;
+        memory.write(caller, ptr as u32 as usize, data)
+            .map_err(|_| wasmi::Error::new("out of bounds memory access"))?;
+    }
+    Ok(data.len() as i32)
+}
+
+/// Messages are JSON chat components, or plain text if they don't parse as one.
+fn read_chat(caller: &Caller<HostState>, ptr: i32, len: i32) -> HostResult<serde_json::Value> {
+    let text = read_str(caller, ptr, len)?;
+    match serde_json::from_str(&text) {
+        Ok(value @ (serde_json::Value::Object(_) | serde_json::Value::Array(_) | serde_json::Value::String(_))) => Ok(value),
+        _ => Ok(json!({ "text": text })),
+    }
+}
+
+/// Join settings as JSON, with the same fields as the table Lua `preLogin`
+/// handlers get.
+fn join_settings_json(settings: &JoinSettings) -> serde_json::Value {
+    let (x, y, z) = settings.position;
+    let (yaw, pitch) = settings.rotation;
+    json!({
+        "world": settings.world,
+        "x": x, "y": y, "z": z,
+        "yaw": yaw, "pitch": pitch,
+        "gamemode": settings.gamemode.name(),
+        "hardcore": settings.hardcore,
+        "viewDistance": settings.view_distance,
+        "reducedDebugInfo": settings.reduced_debug_info,
+        "respawnScreen": settings.respawn_screen,
+    })
+}
+
+/// Apply the fields set in `json` to `settings`.
+fn update_join_settings(settings: &mut JoinSettings, json: &serde_json::Value) -> Result<(), String> {
+    let object = json.as_object().ok_or("join settings must be a JSON object")?;
+    for (key, value) in object {
+        let invalid = || format!("invalid value for {}: {}", key, value);
+        let number = || value.as_f64().ok_or_else(invalid);
+        let flag = || value.as_bool().ok_or_else(invalid);
+        match key.as_str() {
+            "world" => settings.world = value.as_str().ok_or_else(invalid)?.to_owned(),
+            "x" => settings.position.0 = number()?,
+            "y" => settings.position.1 = number()?,
+            "z" => settings.position.2 = number()?,
+            "yaw" => settings.rotation.0 = number()? as f32,
+            "pitch" => settings.rotation.1 = number()? as f32,
+            "gamemode" => settings.gamemode = value.as_str().ok_or_else(invalid)?.parse::<GameMode>()?,
+            "hardcore" => settings.hardcore = flag()?,
+            "viewDistance" => settings.view_distance = value.as_u64()
+                .filter(|distance| (2..=32).contains(distance))
+                .ok_or_else(|| format!("viewDistance {} must be between 2 and 32", value))? as u8,
+            "reducedDebugInfo" => settings.reduced_debug_info = flag()?,
+            "respawnScreen" => settings.respawn_screen = flag()?,
+            _ => return Err(format!("unknown join setting '{}'", key)),
+        }
+    }
+    Ok(())
+}
+
+fn add_host_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    linker.func_wrap("qc", "log", |caller: Caller<HostState>, level: i32, ptr: i32, len: i32| -> HostResult<()> {
+        let msg = read_str(&caller, ptr, len)?;
+        let target = format!("pl::{}", caller.data().id);
+        match level {
+            0 => trace!(target: &target, "{}", msg),
+            1 => debug!(target: &target, "{}", msg),
+            2 => info!(target: &target, "{}", msg),
+            3 => warn!(target: &target, "{}", msg),
+            _ => error!(target: &target, "{}", msg),
+        }
+        Ok(())
+    })?;
+    linker.func_wrap("qc", "version", |mut caller: Caller<HostState>, out: i32, cap: i32| -> HostResult<i32> {
+        write_out(&mut caller, out, cap, VERSION.as_bytes())
+    })?;
+    linker.func_wrap("qc", "is_online", |caller: Caller<HostState>, player: i32, player_len: i32| -> HostResult<i32> {
+        let key = read_str(&caller, player, player_len)?;
+        let online = caller.data().players.borrow().find(&key).is_some();
+        Ok(online as i32)
+    })?;
+    linker.func_wrap("qc", "get_player", |mut caller: Caller<HostState>, player: i32, player_len: i32, out: i32, cap: i32| -> HostResult<i32> {
+        let key = read_str(&caller, player, player_len)?;
+        let uuid = caller.data().players.borrow().find(&key).map(|p| p.player.uuid.to_string());
+        match uuid {
+            Some(uuid) => write_out(&mut caller, out, cap, uuid.as_bytes()),
+            None => Ok(-1),
+        }
+    })?;
+    linker.func_wrap("qc", "get_worlds", |mut caller: Caller<HostState>, out: i32, cap: i32| -> HostResult<i32> {
+        let names: Vec<String> = caller.data().worlds.borrow().names().map(str::to_owned).collect();
+        write_out(&mut caller, out, cap, json!(names).to_string().as_bytes())
+    })?;
+    linker.func_wrap("qc", "get_world_players", |mut caller: Caller<HostState>, world: i32, world_len: i32, out: i32, cap: i32| -> HostResult<i32> {
+        let world = read_str(&caller, world, world_len)?;
+        let uuids: Vec<String> = caller.data().players.borrow().iter()
+            .filter(|p| p.world == world)
+            .map(|p| p.player.uuid.to_string())
+            .collect();
+        write_out(&mut caller, out, cap, json!(uuids).to_string().as_bytes())
+    })?;
+    linker.func_wrap("qc", "block_state", |caller: Caller<HostState>, name: i32, name_len: i32| -> HostResult<i32> {
+        let name = read_str(&caller, name, name_len)?;
+        let state = caller.data().worlds.borrow().blocks().state(&name);
+        Ok(state.map_or(-1, |state| state as i32))
+    })?;
+    linker.func_wrap("qc", "set_block", |caller: Caller<HostState>, world: i32, world_len: i32, x: i32, y: i32, z: i32, state: i32| -> HostResult<i32> {
+        let world = read_str(&caller, world, world_len)?;
+        let data = caller.data();
+        let result = set_block(&data.players.borrow(), &mut data.worlds.borrow_mut(), &world, (x, y, z), state as u32);
+        if let Err(e) = &result {
+            debug!(target: &format!("pl::{}", data.id), "Couldn't set block: {}", e);
+        }
+        Ok(result.is_ok() as i32)
+    })?;
+    linker.func_wrap("qc", "join_settings", |mut caller: Caller<HostState>, out: i32, cap: i32| -> HostResult<i32> {
+        let settings = caller.data().join.as_ref()
+            .map(join_settings_json)
+            .ok_or_else(|| wasmi::Error::new("join settings are only available in pre_login"))?;
+        write_out(&mut caller, out, cap, settings.to_string().as_bytes())
+    })?;
+    linker.func_wrap("qc", "set_join_settings", |mut caller: Caller<HostState>, ptr: i32, len: i32| -> HostResult<()> {
+        let json: serde_json::Value = serde_json::from_slice(&read_bytes(&caller, ptr, len)?)
+            .map_err(|e| wasmi::Error::new(format!("invalid JSON value: {}", e)))?;
+        let settings = caller.data_mut().join.as_mut()
+            .ok_or_else(|| wasmi::Error::new("join settings are only available in pre_login"))?;
+        update_join_settings(settings, &json).map_err(wasmi::Error::new)
+    })?;
+    linker.func_wrap("qc", "send_message", |caller: Caller<HostState>, player: i32, player_len: i32, msg: i32, msg_len: i32| -> HostResult<i32> {
+        let key = read_str(&caller, player, player_len)?;
+        let message = read_chat(&caller, msg, msg_len)?;
+        Ok(with_player(&caller.data().players, &key, |p| p.send_message(message)).0 as i32)
+    })?;
+    linker.func_wrap("qc", "send_plugin_message", |caller: Caller<HostState>, player: i32, player_len: i32, channel: i32, channel_len: i32, data: i32, data_len: i32| -> HostResult<i32> {
+        let key = read_str(&caller, player, player_len)?;
+        let channel = read_str(&caller, channel, channel_len)?;
+        let data = read_bytes(&caller, data, data_len)?;
+        Ok(with_player(&caller.data().players, &key, |p| p.send_plugin_message(channel, data)).0 as i32)
+    })?;
+    linker.func_wrap("qc", "disconnect", |caller: Caller<HostState>, player: i32, player_len: i32, reason: i32, reason_len: i32| -> HostResult<i32> {
+        let key = read_str(&caller, player, player_len)?;
+        let reason = if reason_len > 0 { read_chat(&caller, reason, reason_len)? } else { default_kick_reason() };
+        Ok(with_player(&caller.data().players, &key, |p| p.disconnect(reason)).0 as i32)
+    })?;
+    linker.func_wrap("qc", "teleport", |caller: Caller<HostState>, player: i32, player_len: i32, x: f64, y: f64, z: f64, yaw: f32, pitch: f32| -> HostResult<i32> {
+        let key = read_str(&caller, player, player_len)?;
+        Ok(with_player(&caller.data().players, &key, |p| p.teleport(x, y, z, yaw, pitch)).0 as i32)
+    })?;
+    linker.func_wrap("qc", "broadcast", |caller: Caller<HostState>, msg: i32, msg_len: i32| -> HostResult<i32> {
+        let message = read_chat(&caller, msg, msg_len)?;
+        Ok(caller.data().players.borrow().broadcast(SystemChatMessage { message, overlay: false }) as i32)
+    })?;
+    linker.func_wrap("qc", "add_command", |caller: Caller<HostState>, name: i32, name_len: i32| -> HostResult<i32> {
+        let name = read_str(&caller, name, name_len)?;
+        match &caller.data().registry {
+            Some(registry) => Ok(registry.add_command(&name) as i32),
+            None => Err(wasmi::Error::new("commands can only be added from register_commands")),
+        }
+    })?;
+    linker.func_wrap("qc", "storage_get", |mut caller: Caller<HostState>, key: i32, key_len: i32, out: i32, cap: i32| -> HostResult<i32> {
+        let key = read_str(&caller, key, key_len)?;
+        let state = caller.data();
+        let storage = state.storage.borrow_mut().open(&state.id).map_err(wasmi::Error::new)?;
+        let value = storage.borrow().get(&key).map(|v| v.to_string());
+        match value {
+            Some(value) => write_out(&mut caller, out, cap, value.as_bytes()),
+            None => Ok(-1),
+        }
+    })?;
+    linker.func_wrap("qc", "storage_set", |caller: Caller<HostState>, key: i32, key_len: i32, value: i32, value_len: i32| -> HostResult<()> {
+        let key = read_str(&caller, key, key_len)?;
+        let state = caller.data();
+        let storage = state.storage.borrow_mut().open(&state.id).map_err(wasmi::Error::new)?;
+        if value_len == 0 {
+            storage.borrow_mut().remove(&key);
+        } else {
+            let value = serde_json::from_slice(&read_bytes(&caller, value, value_len)?)
+                .map_err(|e| wasmi::Error::new(format!("invalid JSON value: {}", e)))?;
+            storage.borrow_mut().set(key, value);
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+impl WasmPlugin {
+    /// Instantiate a plugin whose metadata has already been checked.
+    pub fn load(path: &Path, meta: Metadata, players: SharedPlayers, worlds: SharedWorlds, storage: Rc<RefCell<StorageRegistry>>, limits: WasmLimits) -> Result<Self, Box<dyn std::error::Error>> {
+        let id = meta.id;
+
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &fs::read(path)?[..])?;
+        let state = HostState {
+            id: id.clone(),
+            players,
+            worlds,
+            storage,
+            registry: None,
+            join: None,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(limits.memory)
+                .trap_on_grow_failure(true)
+                .build(),
+        };
+        let mut store = Store::new(&engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(limits.fuel).map_err(|e| e.to_string())?;
+        let mut linker = Linker::new(&engine);
+        add_host_functions(&mut linker)?;
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+
+        Ok(Self {
+            id,
+            name: meta.name,
+            version: meta.version,
+            description: meta.description,
+            authors: meta.authors,
+            store,
+            instance,
+            fuel: limits.fuel,
+        })
+    }
+
+    /// Copy bytes into the plugin's memory, using its `qc_alloc` export.
+    fn write(&mut self, data: &[u8]) -> Result<[Val; 2], wasmi::Error> {
+        let alloc = self.instance.get_typed_func::<i32, i32>(&self.store, "qc_alloc")?;
+        let ptr = alloc.call(&mut self.store, data.len() as i32)?;
+        let memory = self.instance.get_memory(&self.store, "memory")
+            .ok_or_else(|| wasmi::Error::new("plugin does not export its memory"))?;
+        memory.write(&mut self.store, ptr as u32 as usize, data)
+            .map_err(|_| wasmi::Error::new("qc_alloc returned an out of bounds pointer"))?;
+        Ok([Val::I32(ptr), Val::I32(data.len() as i32)])
+    }
+
+    /// Call one of the plugin's exported handlers, if it has one. Each call
+    /// gets a fresh allowance of fuel. `args` can only be computed once the
+    /// handler is known to exist, since they are written into the plugin's
+    /// memory. The handler's results are written to `results`.
+    fn call(&mut self, name: &str, args: impl FnOnce(&mut Self) -> Result<Vec<Val>, wasmi::Error>, results: &mut [Val]) -> HandlerResult {
+        let Some(func) = self.instance.get_func(&self.store, name) else {
+            return Ok(())
+        };
+        self.store.set_fuel(self.fuel).map_err(|e| e.to_string())?;
+        let result = args(self).and_then(|args| func.call(&mut self.store, &args, results));
+        match result {
+            Err(e) if e.as_trap_code() == Some(TrapCode::OutOfFuel) => Err(format!("handler ran out of fuel ({} units)", self.fuel).into()),
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn player_args(&mut self, player: &Player) -> Result<Vec<Val>, wasmi::Error> {
+        let mut args = self.write(player.uuid.to_string().as_bytes())?.to_vec();
+        args.extend(self.write(player.name.as_bytes())?);
+        Ok(args)
+    }
+
+    /// Call a block event handler, which gets the player, the world, the
+    /// position, the clicked face (empty if there isn't one) and the state
+    /// (-1 if unknown), and returns an `i32`.
+    fn block_event(&mut self, name: &str, player: &Player, event: &BlockEvent) -> Result<Option<i32>, Box<dyn std::error::Error>> {
+        let mut results = [Val::I32(0)];
+        let mut called = false;
+        self.call(name, |this| {
+            called = true;
+            let (x, y, z) = event.position;
+            let mut args = this.player_args(player)?;
+            args.extend(this.write(event.world.as_bytes())?);
+            args.extend([Val::I32(x), Val::I32(y), Val::I32(z)]);
+            args.extend(this.write(event.face.map_or("", |f| f.name()).as_bytes())?);
+            args.push(Val::I32(event.state.map_or(-1, |state| state as i32)));
+            Ok(args)
+        }, &mut results)?;
+        Ok(results[0].i32().filter(|_| called))
+    }
+}
+
+impl Plugin for WasmPlugin {
+    fn id(&self) -> &str { &self.id }
+    fn name(&self) -> &str { &self.name }
+    fn version(&self) -> &str { &self.version }
+    fn description(&self) -> Option<&str> { self.description.as_deref() }
+    fn authors(&self) -> &[String] { &self.authors }
+
+    fn init(&mut self, _: &Context) -> HandlerResult {
+        self.call("init", |_| Ok(Vec::new()), &mut [])
+    }
+
+    fn register_commands(&mut self, registry: &CommandRegistry) -> HandlerResult {
+        self.store.data_mut().registry = Some(registry.clone());
+        let result = self.call("register_commands", |_| Ok(Vec::new()), &mut []);
+        self.store.data_mut().registry = None;
+        result
+    }
+
+    fn pre_login(&mut self, _: &Context, player: &Player, settings: &mut JoinSettings) -> HandlerResult {
+        self.store.data_mut().join = Some(settings.clone());
+        let result = self.call("pre_login", |this| this.player_args(player), &mut []);
+        // changes from a handler that fails are discarded
+        let changed = self.store.data_mut().join.take();
+        if let (Ok(()), Some(changed)) = (&result, changed) {
+            *settings = changed;
+        }
+        result
+    }
+
+    fn player_join(&mut self, _: &Context, player: &Player, replay: bool) -> HandlerResult {
+        self.call("player_join", |this| {
+            let mut args = this.player_args(player)?;
+            args.push(Val::I32(replay as i32));
+            Ok(args)
+        }, &mut [])
+    }
+
+    fn player_leave(&mut self, _: &Context, player: &Player) -> HandlerResult {
+        self.call("player_leave", |this| this.player_args(player), &mut [])
+    }
+
+    fn chat_message(&mut self, _: &Context, player: &Player, message: &str) -> HandlerResult {
+        self.call("chat_message", |this| {
+            let mut args = this.player_args(player)?;
+            args.extend(this.write(message.as_bytes())?);
+            Ok(args)
+        }, &mut [])
+    }
+
+    fn command(&mut self, _: &Context, player: &Player, command: &str, args: &str) -> HandlerResult {
+        self.call("command", |this| {
+            let mut all = this.player_args(player)?;
+            all.extend(this.write(command.as_bytes())?);
+            all.extend(this.write(args.as_bytes())?);
+            Ok(all)
+        }, &mut [])
+    }
+
+    fn plugin_message(&mut self, _: &Context, player: &Player, channel: &str, data: &[u8]) -> HandlerResult {
+        self.call("plugin_message", |this| {
+            let mut args = this.player_args(player)?;
+            args.extend(this.write(channel.as_bytes())?);
+            args.extend(this.write(data)?);
+            Ok(args)
+        }, &mut [])
+    }
+
+    fn block_interact(&mut self, _: &Context, player: &Player, event: &mut BlockEvent) -> HandlerResult {
+        if let Some(cancel) = self.block_event("block_interact", player, event)? {
+            event.cancelled = cancel != 0;
+        }
+        Ok(())
+    }
+
+    fn block_break(&mut self, _: &Context, player: &Player, event: &mut BlockEvent) -> HandlerResult {
+        if let Some(cancel) = self.block_event("block_break", player, event)? {
+            event.cancelled = cancel != 0;
+        }
+        Ok(())
+    }
+
+    fn block_place(&mut self, _: &Context, player: &Player, event: &mut BlockEvent) -> HandlerResult {
+        if let Some(state) = self.block_event("block_place", player, event)? {
+            event.state = u32::try_from(state).ok();
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, _: &Context) -> HandlerResult {
+        self.call("tick", |_| Ok(Vec::new()), &mut [])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> JoinSettings {
+        JoinSettings {
+            world: "qc:world".to_owned(),
+            position: (0.5, 64.0, 0.5),
+            rotation: (0.0, 0.0),
+            gamemode: GameMode::Survival,
+            hardcore: false,
+            view_distance: 8,
+            reduced_debug_info: false,
+            respawn_screen: true,
+        }
+    }
+
+    #[test]
+    fn join_settings_round_trip() {
+        let mut changed = settings();
+        update_join_settings(&mut changed, &json!({ "gamemode": "creative", "y": 100, "viewDistance": 4 })).unwrap();
+        assert_eq!((changed.gamemode, changed.position.1, changed.view_distance), (GameMode::Creative, 100.0, 4));
+        let mut copy = settings();
+        update_join_settings(&mut copy, &join_settings_json(&changed)).unwrap();
+        assert_eq!(join_settings_json(&copy), join_settings_json(&changed));
+    }
+
+    #[test]
+    fn invalid_join_settings() {
+        for bad in [json!([]), json!({ "viewDistance": 64 }), json!({ "gamemode": "flying" }), json!({ "x": "0" }), json!({ "speed": 1 })] {
+            assert!(update_join_settings(&mut settings(), &bad).is_err(), "{} was accepted", bad);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use uuid::Uuid;
 
+use crate::world::LightData;
+
 use super::{data::{PacketEncoder, finalize_packet}, Position};
 
 pub trait ClientBoundPacket: std::fmt::Debug {
@@ -150,6 +152,41 @@ impl ClientBoundPacket for LoginPlay {
     fn packet_id(&self) -> i32 { 0x24 }
 }
 
+/// Move the player to another dimension, which clears everything the client
+/// has loaded.
+#[derive(Debug)]
+pub struct Respawn {
+    pub dimension_type: String,
+    pub dimension_name: String,
+    pub seed_hash: i64,
+    pub gamemode: u8,
+    pub prev_gamemode: u8,
+    pub is_debug: bool,
+    pub is_flat: bool,
+    pub copy_metadata: bool,
+    pub death_location: Option<(String, Position)>
+}
+
+impl ClientBoundPacket for Respawn {
+    fn encode(&self, encoder: &mut impl PacketEncoder) {
+        encoder.write_string(32767, &self.dimension_type);
+        encoder.write_string(32767, &self.dimension_name);
+        encoder.write_long(self.seed_hash);
+        encoder.write_ubyte(self.gamemode);
+        encoder.write_ubyte(self.prev_gamemode);
+        encoder.write_bool(self.is_debug);
+        encoder.write_bool(self.is_flat);
+        encoder.write_bool(self.copy_metadata);
+        encoder.write_bool(self.death_location.is_some());
+        if let Some(dl) = &self.death_location {
+            encoder.write_string(32767, &dl.0);
+            encoder.write_position(dl.1);
+        }
+    }
+
+    fn packet_id(&self) -> i32 { 0x3d }
+}
+
 #[derive(Debug)]
 pub struct CPluginMessage {
     pub channel: String,
@@ -198,6 +235,7 @@ pub struct ChunkData {
     pub z: i32,
     pub heightmap: nbt::Blob,
     pub chunk_data: Vec<u8>,
+    pub light: LightData,
 }
 
 impl ClientBoundPacket for ChunkData {
@@ -209,22 +247,103 @@ impl ClientBoundPacket for ChunkData {
         encoder.write_bytes(&self.chunk_data);
         // number of block entities
         encoder.write_varint(0);
-        // trust edges
-        encoder.write_bool(true);
-        // light masks
-        encoder.write_varint(0);
-        encoder.write_varint(0);
-        encoder.write_varint(0);
-        encoder.write_varint(0);
-        // sky light array
-        encoder.write_varint(0);
-        // block light array
-        encoder.write_varint(0);
+        // trust edges: light is computed for each chunk on its own, so the
+        // client has to fix up light spreading across chunk borders
+        encoder.write_bool(false);
+        self.light.encode(encoder);
     }
 
     fn packet_id(&self) -> i32 { 0x20 }
 }
 
+/// New light for a chunk the client already has, such as after blocks in
+/// it change.
+#[derive(Debug)]
+pub struct UpdateLight {
+    pub x: i32,
+    pub z: i32,
+    pub light: LightData,
+}
+
+impl ClientBoundPacket for UpdateLight {
+    fn encode(&self, encoder: &mut impl PacketEncoder) {
+        encoder.write_varint(self.x);
+        encoder.write_varint(self.z);
+        // trust edges: light is computed for each chunk on its own, so the
+        // client has to fix up light spreading across chunk borders
+        encoder.write_bool(false);
+        self.light.encode(encoder);
+    }
+
+    fn packet_id(&self) -> i32 { 0x23 }
+}
+
+/// Change one block the client has loaded.
+#[derive(Debug)]
+pub struct BlockUpdate {
+    pub pos: Position,
+    pub state: i32,
+}
+
+impl ClientBoundPacket for BlockUpdate {
+    fn encode(&self, encoder: &mut impl PacketEncoder) {
+        encoder.write_position(self.pos);
+        encoder.write_varint(self.state);
+    }
+
+    fn packet_id(&self) -> i32 { 0x09 }
+}
+
+/// Tell the client the server has handled its block changes up to
+/// `sequence`. The client then drops the blocks it predicted and keeps the
+/// ones the server sent.
+#[derive(Debug)]
+pub struct AcknowledgeBlockChange {
+    pub sequence: i32,
+}
+
+impl ClientBoundPacket for AcknowledgeBlockChange {
+    fn encode(&self, encoder: &mut impl PacketEncoder) {
+        encoder.write_varint(self.sequence);
+    }
+
+    fn packet_id(&self) -> i32 { 0x05 }
+}
+
+#[derive(Debug)]
+pub struct UnloadChunk {
+    pub x: i32,
+    pub z: i32,
+}
+
+impl ClientBoundPacket for UnloadChunk {
+    fn encode(&self, encoder: &mut impl PacketEncoder) {
+        encoder.write_int(self.x);
+        encoder.write_int(self.z);
+    }
+
+    fn packet_id(&self) -> i32 { 0x1b }
+}
+
+#[derive(Debug)]
+pub struct GameEvent {
+    pub event: u8,
+    pub value: f32,
+}
+
+impl GameEvent {
+    pub const CHANGE_GAMEMODE: u8 = 3;
+}
+
+impl ClientBoundPacket for GameEvent {
+    fn encode(&self, encoder: &mut impl PacketEncoder) {
+        encoder.write_ubyte(self.event);
+        encoder.write_float(self.value);
+    }
+
+    fn packet_id(&self) -> i32 { 0x1c }
+}
+
 #[derive(Debug)]
 pub struct KeepAlive {
     pub data: i64
@@ -269,6 +388,21 @@ impl ClientBoundPacket for Disconnect {
     fn packet_id(&self) -> i32 { 0x17 }
 }
 
+#[derive(Debug)]
+pub struct SetCenterChunk {
+    pub x: i32,
+    pub z: i32,
+}
+
+impl ClientBoundPacket for SetCenterChunk {
+    fn encode(&self, encoder: &mut impl PacketEncoder) {
+        encoder.write_varint(self.x);
+        encoder.write_varint(self.z);
+    }
+
+    fn packet_id(&self) -> i32 { 0x4a }
+}
+
 #[derive(Debug)]
 pub struct SetDefaultSpawnPosition {
     pub pos: Position,
@@ -284,7 +418,7 @@ impl ClientBoundPacket for SetDefaultSpawnPosition {
     fn packet_id(&self) -> i32 { 0x4c }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SystemChatMessage {
     pub message: serde_json::Value,
     pub overlay: bool
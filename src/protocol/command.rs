@@ -18,6 +18,12 @@ impl ClientBoundPacket for Commands {
     fn packet_id(&self) -> i32 { 0x0e }
 }
 
+impl Default for Commands {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Commands {
     pub fn new() -> Self {
         let root = CommandNode {
@@ -132,7 +138,6 @@ impl CommandNode {
     }
 }
 
-#[allow(unused)]
 #[derive(Debug, Clone, Copy)]
 pub enum Parser {
     Bool,
@@ -183,7 +188,6 @@ impl Parser {
     }
 }
 
-#[allow(unused)]
 #[derive(Debug, Clone, Copy)]
 pub enum StringKind {
     Single,
@@ -138,7 +138,6 @@ pub struct PacketDecoder {
     packet_id: i32,
 }
 
-#[allow(unused)]
 impl PacketDecoder {
     pub fn decode(read: &mut impl Read) -> Result<PacketDecoder, std::io::Error> {
         let size = read_varint(read)? as usize;
@@ -267,6 +266,15 @@ impl PacketDecoder {
         result
     }
 
+    pub fn read_position(&mut self) -> Position {
+        let value = self.read_long();
+        Position {
+            x: (value >> 38) as i32,
+            y: (value << 52 >> 52) as i16,
+            z: (value << 26 >> 38) as i32,
+        }
+    }
+
     pub fn read_string(&mut self) -> String {
         let len = self.read_varint() as usize;
         String::from_utf8(self.read_bytes(len).to_vec()).unwrap()
@@ -3,6 +3,7 @@ pub mod command;
 pub mod data;
 pub mod serverbound;
 pub mod clientbound;
+pub mod registry;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum NetworkState {
@@ -18,3 +19,43 @@ pub struct Position {
     pub y: i16,
     pub z: i32
 }
+
+/// A side of a block.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlockFace {
+    Down,
+    Up,
+    North,
+    South,
+    West,
+    East,
+}
+
+impl BlockFace {
+    pub fn from_id(id: i32) -> Option<Self> {
+        [Self::Down, Self::Up, Self::North, Self::South, Self::West, Self::East].get(id as usize).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Down => "down",
+            Self::Up => "up",
+            Self::North => "north",
+            Self::South => "south",
+            Self::West => "west",
+            Self::East => "east",
+        }
+    }
+
+    /// The direction this face points in.
+    pub fn offset(self) -> (i32, i32, i32) {
+        match self {
+            Self::Down => (0, -1, 0),
+            Self::Up => (0, 1, 0),
+            Self::North => (0, 0, -1),
+            Self::South => (0, 0, 1),
+            Self::West => (-1, 0, 0),
+            Self::East => (1, 0, 0),
+        }
+    }
+}
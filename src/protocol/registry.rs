@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// The vanilla 1.19.3 registries, which [`RegistryCodec::vanilla`] starts
+/// from.
+const VANILLA: &[u8] = include_bytes!("../resources/registry_codec.nbt");
+
+/// Registries sent to the client on login. Protocol 761 has no damage type
+/// registry; it was added in 1.19.4.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegistryCodec {
+    #[serde(rename = "minecraft:chat_type")]
+    pub chat_types: Registry<ChatType>,
+    #[serde(rename = "minecraft:dimension_type")]
+    pub dimension_types: Registry<DimensionType>,
+    #[serde(rename = "minecraft:worldgen/biome")]
+    pub biomes: Registry<Biome>,
+}
+
+impl Default for RegistryCodec {
+    fn default() -> Self {
+        Self::vanilla()
+    }
+}
+
+impl RegistryCodec {
+    /// The chat types, dimension types and biomes of vanilla 1.19.3.
+    pub fn vanilla() -> Self {
+        nbt::from_reader(VANILLA).expect("built-in registry codec is valid")
+    }
+
+    /// The codec as NBT, as sent in the Login (play) packet.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        nbt::to_writer(&mut data, self, None).expect("registry codec can be written as NBT");
+        data
+    }
+}
+
+/// One registry: named elements, each with a numeric ID.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Registry<T> {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: Vec<RegistryEntry<T>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegistryEntry<T> {
+    pub name: String,
+    pub id: i32,
+    pub element: T,
+}
+
+impl<T> Registry<T> {
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.value.iter().find(|e| e.name == name).map(|e| &e.element)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut T> {
+        self.value.iter_mut().find(|e| e.name == name).map(|e| &mut e.element)
+    }
+
+    pub fn id(&self, name: &str) -> Option<i32> {
+        self.value.iter().find(|e| e.name == name).map(|e| e.id)
+    }
+
+    /// IDs of every element, by name.
+    pub fn ids(&self) -> HashMap<String, u32> {
+        self.value.iter().map(|e| (e.name.clone(), e.id as u32)).collect()
+    }
+
+    /// Add an element with the next free ID, or replace the element with
+    /// this name if there is one.
+    pub fn insert(&mut self, name: &str, element: T) {
+        match self.get_mut(name) {
+            Some(existing) => *existing = element,
+            None => {
+                let id = self.value.iter().map(|e| e.id + 1).max().unwrap_or(0);
+                self.value.push(RegistryEntry { name: name.to_owned(), id, element });
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatType {
+    pub chat: ChatDecoration,
+    pub narration: ChatDecoration,
+}
+
+/// How a chat message is shown or narrated: a translation key and the
+/// parameters (`sender`, `target`, `content`) it is given.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatDecoration {
+    pub translation_key: String,
+    pub parameters: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style: Option<ChatStyle>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatStyle {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub italic: Option<bool>,
+}
+
+/// The properties of a dimension: its height, lighting and sky.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DimensionType {
+    pub piglin_safe: bool,
+    pub natural: bool,
+    /// Light level everywhere, from 0 to 1.
+    pub ambient_light: f32,
+    /// Time of day the sky is fixed at, in ticks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixed_time: Option<i64>,
+    pub infiniburn: String,
+    pub respawn_anchor_works: bool,
+    pub has_skylight: bool,
+    pub bed_works: bool,
+    /// Sky and fog rendering: `minecraft:overworld`, `minecraft:the_nether`
+    /// or `minecraft:the_end`.
+    pub effects: String,
+    pub has_raids: bool,
+    /// Lowest block, a multiple of 16.
+    pub min_y: i32,
+    /// Height in blocks, a multiple of 16.
+    pub height: i32,
+    /// Height chorus fruit and nether portals can reach.
+    pub logical_height: i32,
+    pub coordinate_scale: f64,
+    pub ultrawarm: bool,
+    pub has_ceiling: bool,
+    pub monster_spawn_light_level: LightLevel,
+    pub monster_spawn_block_light_limit: i32,
+}
+
+impl DimensionType {
+    /// Check the limits the client places on a dimension type.
+    pub fn check(&self) -> Result<(), String> {
+        if self.min_y % 16 != 0 || !(-2032..=2016).contains(&self.min_y) {
+            return Err(format!("min_y {} must be a multiple of 16 between -2032 and 2016", self.min_y))
+        }
+        if self.height % 16 != 0 || !(16..=4064).contains(&self.height) {
+            return Err(format!("height {} must be a multiple of 16 between 16 and 4064", self.height))
+        }
+        if self.min_y + self.height > 2032 {
+            return Err(format!("min_y + height must be at most 2032, not {}", self.min_y + self.height))
+        }
+        if self.logical_height > self.height {
+            return Err(format!("logical_height {} is more than the height {}", self.logical_height, self.height))
+        }
+        if !(0.0..=1.0).contains(&self.ambient_light) {
+            return Err(format!("ambient_light {} must be between 0 and 1", self.ambient_light))
+        }
+        Ok(())
+    }
+}
+
+/// A light level, either fixed or picked from a range.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LightLevel {
+    Constant(i32),
+    Uniform {
+        #[serde(rename = "type")]
+        kind: String,
+        value: UniformRange,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UniformRange {
+    pub min_inclusive: i32,
+    pub max_inclusive: i32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Biome {
+    /// `none`, `rain` or `snow`.
+    pub precipitation: String,
+    pub temperature: f32,
+    pub downfall: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature_modifier: Option<String>,
+    pub effects: BiomeEffects,
+}
+
+/// Colors, sounds and particles of a biome. Colors are `0xRRGGBB`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BiomeEffects {
+    pub sky_color: i32,
+    pub fog_color: i32,
+    pub water_color: i32,
+    pub water_fog_color: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grass_color: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub foliage_color: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grass_color_modifier: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ambient_sound: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mood_sound: Option<MoodSound>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub additions_sound: Option<AdditionsSound>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub music: Option<Music>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub particle: Option<BiomeParticle>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MoodSound {
+    pub sound: String,
+    pub tick_delay: i32,
+    pub offset: f64,
+    pub block_search_extent: i32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdditionsSound {
+    pub sound: String,
+    pub tick_chance: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Music {
+    pub sound: String,
+    pub min_delay: i32,
+    pub max_delay: i32,
+    pub replace_current_music: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BiomeParticle {
+    pub probability: f32,
+    pub options: ParticleOptions,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ParticleOptions {
+    #[serde(rename = "type")]
+    pub kind: String,
+}
@@ -1,6 +1,6 @@
 use uuid::Uuid;
 
-use super::{data::PacketDecoder, NetworkState};
+use super::{data::PacketDecoder, BlockFace, NetworkState, Position};
 
 #[derive(Debug)]
 pub struct Handshake {
@@ -20,13 +20,6 @@ impl Handshake {
     }
 }
 
-#[derive(Debug)]
-pub struct SigData {
-    pub timestamp: i64,
-    pub pubkey: Vec<u8>,
-    pub sig: Vec<u8>,
-}
-
 #[derive(Debug)]
 pub struct LoginStart {
     pub name: String,
@@ -93,6 +86,117 @@ impl SPluginMessage {
     }
 }
 
+#[derive(Debug)]
+pub struct ClientInformation {
+    pub locale: String,
+    pub view_distance: i8,
+    pub chat_mode: i32,
+    pub chat_colors: bool,
+    pub skin_parts: u8,
+    pub main_hand: i32,
+    pub text_filtering: bool,
+    pub allow_listing: bool,
+}
+
+impl ClientInformation {
+    pub fn decode(mut decoder: PacketDecoder) -> Self {
+        let locale = decoder.read_string();
+        let view_distance = decoder.read_byte();
+        let chat_mode = decoder.read_varint();
+        let chat_colors = decoder.read_bool();
+        let skin_parts = decoder.read_ubyte();
+        let main_hand = decoder.read_varint();
+        let text_filtering = decoder.read_bool();
+        let allow_listing = decoder.read_bool();
+        Self { locale, view_distance, chat_mode, chat_colors, skin_parts, main_hand, text_filtering, allow_listing }
+    }
+}
+
+/// Any of the player movement packets. Each only sends the parts that have
+/// changed.
+#[derive(Debug)]
+pub struct PlayerMovement {
+    pub position: Option<(f64, f64, f64)>,
+    pub rotation: Option<(f32, f32)>,
+    pub on_ground: bool,
+}
+
+impl PlayerMovement {
+    pub fn decode(mut decoder: PacketDecoder, position: bool, rotation: bool) -> Self {
+        let position = position.then(|| (decoder.read_double(), decoder.read_double(), decoder.read_double()));
+        let rotation = rotation.then(|| (decoder.read_float(), decoder.read_float()));
+        let on_ground = decoder.read_bool();
+        Self { position, rotation, on_ground }
+    }
+}
+
+/// Digging, dropping items and other actions. Only the digging statuses
+/// carry a block position and face.
+#[derive(Debug)]
+pub struct PlayerAction {
+    pub status: i32,
+    pub location: Position,
+    pub face: Option<BlockFace>,
+    /// Acknowledged with [`AcknowledgeBlockChange`](super::clientbound::AcknowledgeBlockChange).
+    pub sequence: i32,
+}
+
+impl PlayerAction {
+    pub const START_DIGGING: i32 = 0;
+    pub const CANCEL_DIGGING: i32 = 1;
+    pub const FINISH_DIGGING: i32 = 2;
+
+    pub fn decode(mut decoder: PacketDecoder) -> Self {
+        let status = decoder.read_varint();
+        let location = decoder.read_position();
+        let face = BlockFace::from_id(decoder.read_byte() as i32);
+        let sequence = decoder.read_varint();
+        Self { status, location, face, sequence }
+    }
+}
+
+/// Right clicking a block, which uses it or places the held block against
+/// it.
+#[derive(Debug)]
+pub struct UseItemOn {
+    /// 0 for the main hand, 1 for the off hand.
+    pub hand: i32,
+    pub location: Position,
+    pub face: BlockFace,
+    /// Where on the face was clicked, each from 0 to 1.
+    pub cursor: (f32, f32, f32),
+    pub inside_block: bool,
+    pub sequence: i32,
+}
+
+impl UseItemOn {
+    pub fn decode(mut decoder: PacketDecoder) -> Self {
+        let hand = decoder.read_varint();
+        let location = decoder.read_position();
+        let face = BlockFace::from_id(decoder.read_varint()).unwrap_or(BlockFace::Up);
+        let cursor = (decoder.read_float(), decoder.read_float(), decoder.read_float());
+        let inside_block = decoder.read_bool();
+        let sequence = decoder.read_varint();
+        Self { hand, location, face, cursor, inside_block, sequence }
+    }
+}
+
+/// An item a creative mode player put in their inventory. Only the item ID
+/// is read, not the count or NBT.
+#[derive(Debug)]
+pub struct SetCreativeModeSlot {
+    pub slot: i16,
+    pub item: Option<i32>,
+}
+
+impl SetCreativeModeSlot {
+    pub fn decode(mut decoder: PacketDecoder) -> Self {
+        let slot = decoder.read_short();
+        let item = decoder.read_bool().then(|| decoder.read_varint());
+        Self { slot, item }
+    }
+}
+
 #[derive(Debug)]
 pub enum ServerBoundPacket {
     Unknown(i32),
@@ -108,7 +212,14 @@ pub enum ServerBoundPacket {
     // play
     ChatMessage(ChatMessage),
     ChatCommand(ChatMessage),
+    ClientInformation(ClientInformation),
     PluginMessage(SPluginMessage),
+    KeepAlive(i64),
+    PlayerMovement(PlayerMovement),
+    PlayerAction(PlayerAction),
+    UseItemOn(UseItemOn),
+    SetHeldItem(i16),
+    SetCreativeModeSlot(SetCreativeModeSlot),
 }
 
 impl ServerBoundPacket {
@@ -140,8 +251,17 @@ impl ServerBoundPacket {
             },
             (NS::Play, 0x04) => ServerBoundPacket::ChatCommand(ChatMessage::decode(decoder)),
             (NS::Play, 0x05) => ServerBoundPacket::ChatMessage(ChatMessage::decode(decoder)),
+            (NS::Play, 0x07) => ServerBoundPacket::ClientInformation(ClientInformation::decode(decoder)),
             (NS::Play, 0x0C) => ServerBoundPacket::PluginMessage(SPluginMessage::decode(decoder)),
-            (NS::Play, id @ (0x11 | 0x13 | 0x14 | 0x15 | 0x1d)) => ServerBoundPacket::Ignored(id),
+            (NS::Play, 0x11) => ServerBoundPacket::KeepAlive(decoder.read_long()),
+            (NS::Play, 0x13) => ServerBoundPacket::PlayerMovement(PlayerMovement::decode(decoder, true, false)),
+            (NS::Play, 0x14) => ServerBoundPacket::PlayerMovement(PlayerMovement::decode(decoder, true, true)),
+            (NS::Play, 0x15) => ServerBoundPacket::PlayerMovement(PlayerMovement::decode(decoder, false, true)),
+            (NS::Play, 0x1C) => ServerBoundPacket::PlayerAction(PlayerAction::decode(decoder)),
+            (NS::Play, id @ 0x1d) => ServerBoundPacket::Ignored(id),
+            (NS::Play, 0x28) => ServerBoundPacket::SetHeldItem(decoder.read_short()),
+            (NS::Play, 0x2B) => ServerBoundPacket::SetCreativeModeSlot(SetCreativeModeSlot::decode(decoder)),
+            (NS::Play, 0x31) => ServerBoundPacket::UseItemOn(UseItemOn::decode(decoder)),
             (_, id) => ServerBoundPacket::Unknown(id),
         }
     }
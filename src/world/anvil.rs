@@ -0,0 +1,332 @@
+use std::{collections::{HashMap, HashSet}, fs::File, io::{BufReader, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use log::warn;
+use nbt::{Blob, Value};
+
+use super::{blocks::BlockRegistry, chunk::AIR, palette::{bits_for, unpack, PaletteKind, PalettedContainer}, Chunk, ChunkSource};
+
+type Compound = nbt::Map<String, Value>;
+
+/// An open region file and the location of each chunk in it.
+struct Region<F = File> {
+    file: F,
+    /// Offset and length of each chunk, in 4 KiB sectors.
+    locations: Vec<(u32, u32)>,
+}
+
+impl Region {
+    fn open(path: &Path) -> std::io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+}
+
+impl<F: Read + Seek> Region<F> {
+    fn new(mut file: F) -> std::io::Result<Self> {
+        let mut header = [0u8; 4096];
+        file.read_exact(&mut header)?;
+        let locations = header.chunks(4)
+            .map(|b| (u32::from_be_bytes([0, b[0], b[1], b[2]]), b[3] as u32))
+            .collect();
+        Ok(Self { file, locations })
+    }
+
+    /// Read and decompress the NBT of a chunk, with region-relative
+    /// coordinates.
+    fn read(&mut self, x: usize, z: usize) -> Result<Option<Blob>, String> {
+        let (offset, sectors) = self.locations[z * 32 + x];
+        if offset == 0 || sectors == 0 {
+            return Ok(None)
+        }
+        self.file.seek(SeekFrom::Start(offset as u64 * 4096)).map_err(|e| e.to_string())?;
+        let mut header = [0u8; 5];
+        self.file.read_exact(&mut header).map_err(|e| e.to_string())?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        if len == 0 || len > sectors as usize * 4096 {
+            return Err(format!("invalid chunk length {}", len))
+        }
+        let mut data = vec![0; len - 1];
+        self.file.read_exact(&mut data).map_err(|e| e.to_string())?;
+        let blob = match header[4] {
+            1 => Blob::from_gzip_reader(&mut &data[..]),
+            2 => Blob::from_zlib_reader(&mut &data[..]),
+            3 => Blob::from_reader(&mut &data[..]),
+            c if c & 128 != 0 => return Err("chunks stored in separate .mcc files are not supported".to_owned()),
+            c => return Err(format!("unsupported compression type {}", c)),
+        };
+        blob.map(Some).map_err(|e| e.to_string())
+    }
+}
+
+/// A vanilla world folder in the Anvil format (1.18 or later), read only.
+/// Chunks are read from its region files as they're needed.
+pub struct AnvilWorld {
+    region_dir: PathBuf,
+    regions: HashMap<(i32, i32), Option<Region>>,
+    blocks: BlockRegistry,
+    biomes: HashMap<String, u32>,
+    /// Block and biome names that have already been warned about.
+    unknown: HashSet<String>,
+}
+
+impl AnvilWorld {
+    /// Open the overworld of a world folder, using `blocks` to map block
+    /// names to states and `biomes` to map biome names to IDs.
+    pub fn open(dir: impl AsRef<Path>, blocks: BlockRegistry, biomes: HashMap<String, u32>) -> Result<Self, Box<dyn std::error::Error>> {
+        let region_dir = dir.as_ref().join("region");
+        if !region_dir.is_dir() {
+            Err(format!("{} is not a directory", region_dir.display()))?
+        }
+        Ok(Self {
+            region_dir,
+            regions: HashMap::new(),
+            blocks,
+            biomes,
+            unknown: HashSet::new(),
+        })
+    }
+
+    /// The world spawn point from `level.dat`.
+    pub fn spawn(dir: impl AsRef<Path>) -> Result<(i32, i32, i32), Box<dyn std::error::Error>> {
+        let path = dir.as_ref().join("level.dat");
+        let file = File::open(&path)
+            .map_err(|e| format!("couldn't open {}: {}", path.display(), e))?;
+        let level = Blob::from_gzip_reader(&mut BufReader::new(file))
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        let Some(Value::Compound(data)) = level.get("Data") else {
+            Err(format!("{} has no Data tag", path.display()))?
+        };
+        let coord = |key: &str| match data.get(key) {
+            Some(Value::Int(v)) => Ok(*v),
+            _ => Err(format!("{} has no {}", path.display(), key)),
+        };
+        Ok((coord("SpawnX")?, coord("SpawnY")?, coord("SpawnZ")?))
+    }
+
+    fn region(&mut self, x: i32, z: i32) -> Option<&mut Region> {
+        let dir = &self.region_dir;
+        self.regions.entry((x, z)).or_insert_with(|| {
+            let path = dir.join(format!("r.{}.{}.mca", x, z));
+            match Region::open(&path) {
+                Ok(region) => Some(region),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    warn!("Couldn't open region file {}: {}", path.display(), e);
+                    None
+                }
+            }
+        }).as_mut()
+    }
+
+    fn warn_unknown(&mut self, kind: &str, name: &str) {
+        if self.unknown.insert(name.to_owned()) {
+            warn!("Unknown {} {} in world, using {}", kind, name, if kind == "block" { "air" } else { "the first biome" });
+        }
+    }
+
+    /// The block state for a palette entry such as
+    /// `{Name: "minecraft:oak_log", Properties: {axis: "y"}}`.
+    fn block_state(&mut self, entry: &Value) -> u32 {
+        let Value::Compound(entry) = entry else { return AIR };
+        let Some(Value::String(name)) = entry.get("Name") else { return AIR };
+        let mut spec = name.clone();
+        if let Some(Value::Compound(properties)) = entry.get("Properties") {
+            let properties: Vec<_> = properties.iter()
+                .filter_map(|(k, v)| match v {
+                    Value::String(v) => Some(format!("{}={}", k, v)),
+                    _ => None,
+                })
+                .collect();
+            if !properties.is_empty() {
+                spec = format!("{}[{}]", name, properties.join(","));
+            }
+        }
+        match self.blocks.state(&spec) {
+            Some(state) => state,
+            None => {
+                self.warn_unknown("block", &spec);
+                AIR
+            }
+        }
+    }
+
+    fn biome(&mut self, entry: &Value) -> u32 {
+        let Value::String(name) = entry else { return 0 };
+        match self.biomes.get(name) {
+            Some(id) => *id,
+            None => {
+                self.warn_unknown("biome", name);
+                0
+            }
+        }
+    }
+
+    /// Convert a stored palette and data array to a network container.
+    /// Block state data uses at least 4 bits per entry, biome data as few
+    /// as needed.
+    fn container(&mut self, tag: &Compound, kind: PaletteKind, min_bits: u8, mut map: impl FnMut(&mut Self, &Value) -> u32) -> Option<PalettedContainer> {
+        let Some(Value::List(entries)) = tag.get("palette") else { return None };
+        if entries.is_empty() {
+            return None
+        }
+        let palette: Vec<u32> = entries.iter().map(|e| map(self, e)).collect();
+        let indices = match tag.get("data") {
+            Some(Value::LongArray(data)) if palette.len() > 1 => {
+                let bits = bits_for(palette.len()).max(min_bits);
+                unpack(data, bits, kind.size).into_iter()
+                    .map(|i| (i as usize).min(palette.len() - 1) as u16)
+                    .collect()
+            }
+            _ => vec![0; kind.size],
+        };
+        Some(PalettedContainer::from_palette(kind, palette, indices))
+    }
+
+    fn convert(&mut self, blob: &Blob, min_y: i32, height: u32) -> Result<Chunk, String> {
+        let Some(Value::List(sections)) = blob.get("sections") else {
+            return Err("no sections (worlds from before 1.18 are not supported)".to_owned())
+        };
+        let mut chunk = Chunk::new(min_y, height, 0);
+        for section in sections {
+            let Value::Compound(section) = section else { continue };
+            let Some(Value::Byte(y)) = section.get("Y") else { continue };
+            let index = *y as i32 - min_y.div_euclid(16);
+            if index < 0 || index >= chunk.sections().len() as i32 {
+                continue
+            }
+            let blocks = match section.get("block_states") {
+                Some(Value::Compound(tag)) => self.container(tag, PaletteKind::BLOCK_STATES, 4, Self::block_state),
+                _ => None,
+            };
+            let biomes = match section.get("biomes") {
+                Some(Value::Compound(tag)) => self.container(tag, PaletteKind::BIOMES, 0, Self::biome),
+                _ => None,
+            };
+            let target = &mut chunk.sections_mut()[index as usize];
+            if let Some(blocks) = blocks {
+                target.blocks = blocks;
+            }
+            if let Some(biomes) = biomes {
+                target.biomes = biomes;
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+impl ChunkSource for AnvilWorld {
+    fn load(&mut self, x: i32, z: i32, min_y: i32, height: u32) -> Option<Chunk> {
+        let region = self.region(x.div_euclid(32), z.div_euclid(32))?;
+        let result = region.read(x.rem_euclid(32) as usize, z.rem_euclid(32) as usize)
+            .and_then(|blob| match blob {
+                Some(blob) => self.convert(&blob, min_y, height).map(Some),
+                None => Ok(None),
+            });
+        match result {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                warn!("Couldn't load chunk {}, {}: {}", x, z, e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::world::palette::pack;
+
+    /// A chunk with stone at 0, 0, 0 and at 15, 15, 15 and air elsewhere.
+    fn chunk_nbt() -> Blob {
+        let block = |name: &str| {
+            let mut entry = Compound::new();
+            entry.insert("Name".to_owned(), Value::String(name.to_owned()));
+            Value::Compound(entry)
+        };
+        let mut block_states = Compound::new();
+        block_states.insert("palette".to_owned(), Value::List(vec![block("minecraft:air"), block("minecraft:stone")]));
+        let indices = (0..4096).map(|i| (i == 0 || i == 4095) as u64);
+        block_states.insert("data".to_owned(), Value::LongArray(pack(indices, 4)));
+        let mut section = Compound::new();
+        section.insert("Y".to_owned(), Value::Byte(0));
+        section.insert("block_states".to_owned(), Value::Compound(block_states));
+        // above the world, so it's skipped
+        let mut stone = Compound::new();
+        stone.insert("palette".to_owned(), Value::List(vec![block("minecraft:stone")]));
+        let mut above = Compound::new();
+        above.insert("Y".to_owned(), Value::Byte(40));
+        above.insert("block_states".to_owned(), Value::Compound(stone));
+        let mut blob = Blob::new();
+        blob.insert("sections", Value::List(vec![Value::Compound(section), Value::Compound(above)])).unwrap();
+        blob
+    }
+
+    /// Put a chunk's location in the header and its data at `sector`.
+    fn add_chunk(file: &mut Vec<u8>, index: usize, sector: u32, sectors: u8, len: u32, compression: u8, data: &[u8]) {
+        file[index * 4..index * 4 + 4].copy_from_slice(&[(sector >> 16) as u8, (sector >> 8) as u8, sector as u8, sectors]);
+        let start = sector as usize * 4096;
+        if file.len() < start + sectors as usize * 4096 {
+            file.resize(start + sectors as usize * 4096, 0);
+        }
+        file[start..start + 4].copy_from_slice(&len.to_be_bytes());
+        file[start + 4] = compression;
+        file[start + 5..start + 5 + data.len()].copy_from_slice(data);
+    }
+
+    fn region() -> Region<Cursor<Vec<u8>>> {
+        let mut raw = Vec::new();
+        chunk_nbt().to_writer(&mut raw).unwrap();
+        let mut zlib = Vec::new();
+        chunk_nbt().to_zlib_writer(&mut zlib).unwrap();
+
+        // location and timestamp tables
+        let mut file = vec![0; 8192];
+        add_chunk(&mut file, 0, 2, 1, raw.len() as u32 + 1, 3, &raw);
+        add_chunk(&mut file, 1, 3, 1, zlib.len() as u32 + 1, 2, &zlib);
+        // 2 has no location, 3 starts past the end of the file
+        file[3 * 4..3 * 4 + 4].copy_from_slice(&[0, 0, 10, 1]);
+        // longer than its sector
+        add_chunk(&mut file, 32, 4, 1, 4097, 3, &raw);
+        // stored in a separate .mcc file
+        add_chunk(&mut file, 33, 5, 1, 1, 0x82, &[]);
+        Region::new(Cursor::new(file)).unwrap()
+    }
+
+    #[test]
+    fn header() {
+        let region = region();
+        assert_eq!(region.locations.len(), 1024);
+        assert_eq!(&region.locations[..4], &[(2, 1), (3, 1), (0, 0), (10, 1)]);
+        assert_eq!(&region.locations[32..34], &[(4, 1), (5, 1)]);
+        assert!(Region::new(Cursor::new(vec![0; 100])).is_err());
+    }
+
+    #[test]
+    fn read_chunks() {
+        let mut region = region();
+        let expected = chunk_nbt();
+        assert_eq!(region.read(0, 0).unwrap(), Some(expected.clone()));
+        assert_eq!(region.read(1, 0).unwrap(), Some(expected));
+        assert_eq!(region.read(2, 0).unwrap(), None);
+        assert_eq!(region.read(31, 31).unwrap(), None);
+        assert!(region.read(3, 0).is_err());
+        assert!(region.read(0, 1).unwrap_err().contains("invalid chunk length"));
+        assert!(region.read(1, 1).unwrap_err().contains(".mcc"));
+    }
+
+    #[test]
+    fn convert_chunk() {
+        let blocks = BlockRegistry::builtin();
+        let stone = blocks.state("stone").unwrap();
+        let mut world = AnvilWorld { region_dir: PathBuf::new(), regions: HashMap::new(), blocks, biomes: HashMap::new(), unknown: HashSet::new() };
+        let chunk = world.convert(&chunk_nbt(), -64, 384).unwrap();
+        assert_eq!(chunk.block(0, 0, 0), stone);
+        assert_eq!(chunk.block(15, 15, 15), stone);
+        assert_eq!(chunk.block(1, 0, 0), AIR);
+        assert_eq!(chunk.block(0, -64, 0), AIR);
+        assert_eq!(chunk.block(0, 319, 0), AIR);
+        assert!(world.convert(&Blob::new(), -64, 384).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use std::{collections::{HashMap, HashSet}, fs, path::Path};
+
+use serde::Deserialize;
+
+/// Block states for protocol 761 that are known without a block report:
+/// name with property values, and state ID. The first state listed for a
+/// block is its default.
+const BUILTIN: &[(&str, u32)] = &[
+    ("air", 0),
+    ("stone", 1),
+    ("granite", 2),
+    ("polished_granite", 3),
+    ("diorite", 4),
+    ("polished_diorite", 5),
+    ("andesite", 6),
+    ("polished_andesite", 7),
+    ("grass_block[snowy=false]", 9),
+    ("grass_block[snowy=true]", 8),
+    ("dirt", 10),
+    ("coarse_dirt", 11),
+    ("podzol[snowy=false]", 13),
+    ("podzol[snowy=true]", 12),
+    ("cobblestone", 14),
+    ("oak_planks", 15),
+    ("spruce_planks", 16),
+    ("birch_planks", 17),
+    ("jungle_planks", 18),
+    ("acacia_planks", 19),
+    ("dark_oak_planks", 20),
+    ("mangrove_planks", 21),
+    ("bedrock", 76),
+    ("water[level=0]", 77),
+    ("lava[level=0]", 93),
+    ("sand", 109),
+    ("red_sand", 110),
+    ("gravel", 111),
+    ("gold_ore", 112),
+    ("deepslate_gold_ore", 113),
+    ("iron_ore", 114),
+    ("deepslate_iron_ore", 115),
+    ("coal_ore", 116),
+    ("deepslate_coal_ore", 117),
+    ("nether_gold_ore", 118),
+];
+
+/// Blocks with no hardness, which survival players break on the first
+/// click. Entries starting with `_` match the end of a name.
+const INSTANT_BREAK: &[&str] = &[
+    "grass", "fern", "dead_bush", "seagrass", "tall_seagrass", "tall_grass", "large_fern",
+    "dandelion", "poppy", "blue_orchid", "allium", "azure_bluet", "_tulip", "oxeye_daisy",
+    "cornflower", "wither_rose", "lily_of_the_valley", "sunflower", "lilac", "rose_bush",
+    "peony", "brown_mushroom", "red_mushroom", "_sapling", "mangrove_propagule", "azalea",
+    "flowering_azalea", "spore_blossom", "small_dripleaf", "hanging_roots", "lily_pad",
+    "wheat", "carrots", "potatoes", "beetroots", "sugar_cane", "sweet_berry_bush",
+    "pumpkin_stem", "melon_stem", "attached_pumpkin_stem", "attached_melon_stem",
+    "nether_wart", "_fungus", "crimson_roots", "warped_roots", "nether_sprouts",
+    "weeping_vines", "weeping_vines_plant", "twisting_vines", "twisting_vines_plant",
+    "cave_vines", "cave_vines_plant", "kelp", "kelp_plant", "sea_pickle", "_coral", "_coral_fan",
+    "_coral_wall_fan", "torch", "_torch", "redstone_wire", "repeater", "comparator",
+    "tripwire", "tripwire_hook", "flower_pot", "end_rod", "fire", "soul_fire", "tnt",
+    "slime_block", "honey_block", "scaffolding", "frogspawn", "structure_void",
+];
+
+#[derive(Clone, Debug, Default)]
+struct BlockStates {
+    /// Property values and state ID, default state first.
+    states: Vec<(Vec<(String, String)>, u32)>,
+}
+
+/// Maps block names such as `minecraft:oak_stairs[facing=east]` to
+/// protocol 761 block state IDs, and item IDs to the blocks they place.
+#[derive(Clone, Debug)]
+pub struct BlockRegistry {
+    blocks: HashMap<String, BlockStates>,
+    /// Item names by item ID, if a registries report was loaded.
+    items: HashMap<i32, String>,
+    /// States of blocks in [`INSTANT_BREAK`].
+    instant: HashSet<u32>,
+}
+
+#[derive(Deserialize)]
+struct ReportBlock {
+    states: Vec<ReportState>,
+}
+
+#[derive(Deserialize)]
+struct ReportRegistry {
+    entries: HashMap<String, ReportEntry>,
+}
+
+#[derive(Deserialize)]
+struct ReportEntry {
+    protocol_id: i32,
+}
+
+#[derive(Deserialize)]
+struct ReportState {
+    id: u32,
+    #[serde(default)]
+    default: bool,
+    #[serde(default)]
+    properties: HashMap<String, String>,
+}
+
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl BlockRegistry {
+    fn new(blocks: HashMap<String, BlockStates>) -> Self {
+        let instant = blocks.iter()
+            .filter(|(name, _)| {
+                let name = name.strip_prefix("minecraft:").unwrap_or(name);
+                name.starts_with("potted_") || INSTANT_BREAK.iter()
+                    .any(|entry| if entry.starts_with('_') { name.ends_with(entry) } else { name == *entry })
+            })
+            .flat_map(|(_, block)| block.states.iter().map(|(_, id)| *id))
+            .collect();
+        Self { blocks, items: HashMap::new(), instant }
+    }
+
+    /// The small table of blocks that is built in: air, the basic stones,
+    /// dirt, planks, bedrock, fluids, sand and gravel, and ores.
+    pub fn builtin() -> Self {
+        let mut blocks: HashMap<String, BlockStates> = HashMap::new();
+        for (spec, id) in BUILTIN {
+            let (name, properties) = spec.split_once('[').unwrap_or((spec, ""));
+            let properties = properties.trim_end_matches(']').split(',')
+                .filter_map(|p| p.split_once('='))
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect();
+            blocks.entry(format!("minecraft:{}", name)).or_default()
+                .states.push((properties, *id));
+        }
+        Self::new(blocks)
+    }
+
+    /// Load every block from a `blocks.json` report, as written by the
+    /// vanilla server's data generator (`--reports`) for 1.19.3. Items are
+    /// read from the `registries.json` report next to it, if there is one.
+    pub fn load_report(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let data = fs::read(path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        let report: HashMap<String, ReportBlock> = serde_json::from_slice(&data)
+            .map_err(|e| format!("couldn't parse {}: {}", path.display(), e))?;
+        let blocks = report.into_iter().map(|(name, block)| {
+            let mut states: Vec<_> = block.states.into_iter()
+                .map(|s| (s.default, (s.properties.into_iter().collect(), s.id)))
+                .collect();
+            // Default state first
+            states.sort_by_key(|(default, (_, id))| (!default, *id));
+            (name, BlockStates { states: states.into_iter().map(|(_, s)| s).collect() })
+        }).collect();
+        let mut registry = Self::new(blocks);
+        let registries = path.with_file_name("registries.json");
+        if registries.exists() {
+            registry.load_items(&registries)?;
+        }
+        Ok(registry)
+    }
+
+    /// Load item IDs from a `registries.json` report.
+    pub fn load_items(&mut self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let data = fs::read(path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        let mut report: HashMap<String, ReportRegistry> = serde_json::from_slice(&data)
+            .map_err(|e| format!("couldn't parse {}: {}", path.display(), e))?;
+        let items = report.remove("minecraft:item")
+            .ok_or_else(|| format!("{} has no item registry", path.display()))?;
+        self.items = items.entries.into_iter().map(|(name, entry)| (entry.protocol_id, name)).collect();
+        Ok(())
+    }
+
+    /// The default state of the block an item places, for items named
+    /// after their block. `None` for other items, or if items weren't
+    /// loaded.
+    pub fn item_block(&self, item: i32) -> Option<u32> {
+        self.state(self.items.get(&item)?).filter(|state| *state != super::chunk::AIR)
+    }
+
+    /// Whether survival players break a block state on the first click,
+    /// without a tool.
+    pub fn breaks_instantly(&self, state: u32) -> bool {
+        self.instant.contains(&state)
+    }
+
+    /// Every block state: the block's name, its property values and the
+    /// state ID.
+    pub fn states(&self) -> impl Iterator<Item = (&str, &[(String, String)], u32)> + '_ {
+        self.blocks.iter().flat_map(|(name, block)| {
+            block.states.iter().map(move |(properties, id)| (name.as_str(), properties.as_slice(), *id))
+        })
+    }
+
+    /// The state ID for a block name with optional properties in brackets.
+    /// A name without a namespace is taken to be in `minecraft`. Properties
+    /// that aren't given are taken from the default state.
+    pub fn state(&self, spec: &str) -> Option<u32> {
+        let (name, properties) = match spec.split_once('[') {
+            Some((name, rest)) => (name, rest.strip_suffix(']')?),
+            None => (spec, ""),
+        };
+        let name = if name.contains(':') { name.to_owned() } else { format!("minecraft:{}", name) };
+        let block = self.blocks.get(&name)?;
+        let (default, id) = block.states.first()?;
+        if properties.is_empty() {
+            return Some(*id)
+        }
+        let mut wanted = default.clone();
+        for property in properties.split(',') {
+            let (key, value) = property.split_once('=')?;
+            let entry = wanted.iter_mut().find(|(k, _)| k == key.trim())?;
+            entry.1 = value.trim().to_owned();
+        }
+        block.states.iter()
+            .find(|(props, _)| wanted.iter().all(|w| props.contains(w)))
+            .map(|(_, id)| *id)
+    }
+}
@@ -0,0 +1,349 @@
+use crate::protocol::data::PacketEncoder;
+
+use super::palette::{pack, PaletteKind, PalettedContainer};
+
+/// The `air` block state.
+pub const AIR: u32 = 0;
+
+/// Bits per block state when states are stored directly, enough for every
+/// block state in 1.19.3.
+pub const BLOCK_STATE_BITS: u8 = 15;
+
+/// A 16x16x16 cube of blocks, with biomes stored per 4x4x4 cell.
+#[derive(Clone, Debug)]
+pub struct ChunkSection {
+    pub blocks: PalettedContainer,
+    pub biomes: PalettedContainer,
+}
+
+impl ChunkSection {
+    pub fn new(block: u32, biome: u32) -> Self {
+        Self {
+            blocks: PalettedContainer::new(PaletteKind::BLOCK_STATES, block),
+            biomes: PalettedContainer::new(PaletteKind::BIOMES, biome),
+        }
+    }
+
+    /// Block state at section-relative coordinates, each in `0..16`.
+    pub fn block(&self, x: usize, y: usize, z: usize) -> u32 {
+        self.blocks.get((y * 16 + z) * 16 + x)
+    }
+
+    pub fn set_block(&mut self, x: usize, y: usize, z: usize, state: u32) {
+        self.blocks.set((y * 16 + z) * 16 + x, state)
+    }
+
+    /// Biome at biome coordinates within the section, each in `0..4`.
+    pub fn biome(&self, x: usize, y: usize, z: usize) -> u32 {
+        self.biomes.get((y * 4 + z) * 4 + x)
+    }
+
+    pub fn set_biome(&mut self, x: usize, y: usize, z: usize, biome: u32) {
+        self.biomes.set((y * 4 + z) * 4 + x, biome)
+    }
+
+    /// Number of blocks that aren't air.
+    pub fn block_count(&self) -> i16 {
+        self.blocks.count_not(AIR) as i16
+    }
+
+    /// Write the section in the network format. `biome_bits` is the number
+    /// of bits for a biome ID, which depends on the registry codec.
+    pub fn encode(&self, encoder: &mut impl PacketEncoder, biome_bits: u8) {
+        encoder.write_short(self.block_count());
+        self.blocks.encode(encoder, BLOCK_STATE_BITS);
+        self.biomes.encode(encoder, biome_bits);
+    }
+}
+
+/// A 16 block wide column of sections, covering the full height of a
+/// dimension.
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    min_y: i32,
+    sections: Vec<ChunkSection>,
+}
+
+impl Chunk {
+    /// An empty chunk for a dimension starting at `min_y` that is `height`
+    /// blocks tall. `height` must be a multiple of 16.
+    pub fn new(min_y: i32, height: u32, biome: u32) -> Self {
+        assert!(height.is_multiple_of(16), "chunk height must be a multiple of 16");
+        Self {
+            min_y,
+            sections: vec![ChunkSection::new(AIR, biome); height as usize / 16],
+        }
+    }
+
+    pub fn min_y(&self) -> i32 {
+        self.min_y
+    }
+
+    pub fn height(&self) -> u32 {
+        self.sections.len() as u32 * 16
+    }
+
+    pub fn sections(&self) -> &[ChunkSection] {
+        &self.sections
+    }
+
+    pub fn sections_mut(&mut self) -> &mut [ChunkSection] {
+        &mut self.sections
+    }
+
+    fn section_index(&self, y: i32) -> Option<usize> {
+        let i = (y - self.min_y).div_euclid(16);
+        (0..self.sections.len() as i32).contains(&i).then_some(i as usize)
+    }
+
+    /// Block state at chunk-relative `x` and `z` (`0..16`) and world `y`.
+    /// Outside the chunk's height this is air.
+    pub fn block(&self, x: usize, y: i32, z: usize) -> u32 {
+        match self.section_index(y) {
+            Some(i) => self.sections[i].block(x, (y - self.min_y) as usize % 16, z),
+            None => AIR,
+        }
+    }
+
+    /// Set a block state, returning false if `y` is outside the chunk.
+    pub fn set_block(&mut self, x: usize, y: i32, z: usize, state: u32) -> bool {
+        match self.section_index(y) {
+            Some(i) => {
+                self.sections[i].set_block(x, (y - self.min_y) as usize % 16, z, state);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the biome of the 4x4x4 cell containing a block, returning false
+    /// if `y` is outside the chunk.
+    pub fn set_biome(&mut self, x: usize, y: i32, z: usize, biome: u32) -> bool {
+        match self.section_index(y) {
+            Some(i) => {
+                let sy = (y - self.min_y) as usize % 16;
+                self.sections[i].set_biome(x / 4, sy / 4, z / 4, biome);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Heights of the highest non-air block in each column, counted from
+    /// the bottom of the chunk and plus one, or 0 for an empty column. The
+    /// columns are ordered by `z * 16 + x`.
+    pub fn heights(&self) -> Vec<u32> {
+        let mut heights = vec![0; 256];
+        for (column, height) in heights.iter_mut().enumerate() {
+            let (x, z) = (column % 16, column / 16);
+            for (i, section) in self.sections.iter().enumerate().rev() {
+                if section.blocks.count_not(AIR) == 0 {
+                    continue
+                }
+                if let Some(y) = (0..16).rev().find(|y| section.block(x, *y, z) != AIR) {
+                    *height = (i * 16 + y + 1) as u32;
+                    break
+                }
+            }
+        }
+        heights
+    }
+
+    /// The heightmaps sent with the chunk. Only `MOTION_BLOCKING` is sent,
+    /// which the client uses for rain and snow; every non-air block counts.
+    pub fn heightmaps(&self) -> nbt::Blob {
+        let bits = (u32::BITS - self.height().leading_zeros()) as u8;
+        let data = pack(self.heights().into_iter().map(u64::from), bits);
+        let mut blob = nbt::Blob::new();
+        blob.insert("MOTION_BLOCKING", data).unwrap();
+        blob
+    }
+
+    /// The `chunk_data` buffer of the chunk data packet: every section,
+    /// from the bottom up.
+    pub fn encode(&self, biome_bits: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        for section in &self.sections {
+            section.encode(&mut data, biome_bits);
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bits per biome ID with the vanilla registry codec
+    const BIOME_BITS: u8 = 6;
+
+    /// Decode a hex fixture, ignoring whitespace. Fixtures are written out
+    /// from the protocol's description of the format rather than produced
+    /// by the encoder.
+    fn hex(text: &str) -> Vec<u8> {
+        let digits: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        digits.chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn empty_section() {
+        // As sent by vanilla for an all-air section in the plains biome.
+        let mut data = Vec::new();
+        ChunkSection::new(AIR, 1).encode(&mut data, BIOME_BITS);
+        assert_eq!(data, [0, 0, 0, 0, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn single_value_section() {
+        // All stone in biome 1: block count 4096, then each container as
+        // 0 bits per entry, the value and an empty data array.
+        let mut data = Vec::new();
+        ChunkSection::new(1, 1).encode(&mut data, BIOME_BITS);
+        assert_eq!(data, hex("1000  00 01 00  00 01 00"));
+    }
+
+    #[test]
+    fn indirect_section() {
+        // The bottom section of a superflat world: bedrock, two layers of
+        // dirt and grass, over air.
+        let mut section = ChunkSection::new(AIR, 1);
+        for x in 0..16 {
+            for z in 0..16 {
+                section.set_block(x, 0, z, 76);
+                section.set_block(x, 1, z, 10);
+                section.set_block(x, 2, z, 10);
+                section.set_block(x, 3, z, 9);
+            }
+        }
+        let mut data = Vec::new();
+        section.encode(&mut data, BIOME_BITS);
+
+        // 1024 blocks; 4 bits per entry; palette of air, bedrock, dirt and
+        // grass; 256 longs of 16 entries, one long per row along x.
+        let expected = hex(&[
+            "0400 04 04 00 4c 0a 09 8002",
+            &"1111111111111111".repeat(16),
+            &"2222222222222222".repeat(32),
+            &"3333333333333333".repeat(16),
+            &"0000000000000000".repeat(192),
+            "00 01 00",
+        ].concat());
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn indirect_bits_grow() {
+        let mut section = ChunkSection::new(AIR, 0);
+        for i in 0..17 {
+            section.set_block(i % 16, i / 16, 0, i as u32 + 1);
+        }
+        let mut data = Vec::new();
+        section.encode(&mut data, BIOME_BITS);
+        // 18 palette entries need 5 bits, 12 to a long.
+        assert_eq!(&data[..4], [0, 17, 5, 18]);
+        let longs = 4096usize.div_ceil(12);
+        assert_eq!(data.len(), 2 + 1 + 1 + 18 + 2 + longs * 8 + 3);
+    }
+
+    #[test]
+    fn direct_section() {
+        let mut section = ChunkSection::new(AIR, 1);
+        for i in 0..300 {
+            section.set_block(i % 16, i / 256, (i / 16) % 16, i as u32 + 1);
+        }
+        let mut data = Vec::new();
+        section.encode(&mut data, BIOME_BITS);
+
+        // 300 blocks; 15 bits per block and no palette; 1024 longs of 4
+        // entries each, with the top 4 bits unused.
+        let expected = hex(&[
+            "012c 0f 8008",
+            "00008000c0010001 00010001c0030005",
+        ].concat());
+        assert_eq!(&data[..expected.len()], expected);
+        let longs = &data[5..5 + 1024 * 8];
+        assert_eq!(&longs[74 * 8..75 * 8], hex("0025804ac0950129"));
+        assert!(longs[75 * 8..].iter().all(|b| *b == 0));
+        assert_eq!(&data[5 + 1024 * 8..], hex("00 01 00"));
+    }
+
+    #[test]
+    fn direct_biomes() {
+        // A registry codec with 100 biomes needs 7 bits per biome ID. 16
+        // different biomes are too many for an indirect palette.
+        let mut section = ChunkSection::new(AIR, 1);
+        for i in 0..64 {
+            section.set_biome(i % 4, i / 16, (i / 4) % 4, (i % 16) as u32 + 1);
+        }
+        let mut data = Vec::new();
+        section.encode(&mut data, 7);
+        let expected = hex("0000 00 00 00  07 08
+            09101c305080c101 02024078e1a3058a 0b14244070c14203 0406080901e3868c
+            0d182c509101c305 060a10182024078e 0f1c3460b1424407 0000000000000010");
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn biomes() {
+        let mut section = ChunkSection::new(AIR, 0);
+        section.set_biome(1, 0, 0, 2);
+        let mut data = Vec::new();
+        section.encode(&mut data, BIOME_BITS);
+        // 2 palette entries take 1 bit each, 64 to a long.
+        assert_eq!(&data[5..], [1, 2, 0, 2, 1, 0, 0, 0, 0, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn compact() {
+        let mut section = ChunkSection::new(AIR, 0);
+        section.set_block(0, 0, 0, 1);
+        section.set_block(0, 0, 0, AIR);
+        section.blocks.compact();
+        let mut data = Vec::new();
+        section.encode(&mut data, BIOME_BITS);
+        assert_eq!(data, [0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn heightmap() {
+        let mut chunk = Chunk::new(-64, 384, 0);
+        chunk.set_block(0, -64, 0, 76);
+        chunk.set_block(1, 63, 0, 1);
+        chunk.set_block(0, 319, 15, 1);
+        assert!(!chunk.set_block(0, 320, 0, 1));
+        let heights = chunk.heights();
+        assert_eq!(heights[0], 1);
+        assert_eq!(heights[1], 128);
+        assert_eq!(heights[15 * 16], 384);
+        assert_eq!(heights[2], 0);
+
+        // 9 bits per height, 7 to a long, 37 longs.
+        let blob = chunk.heightmaps();
+        let Some(nbt::Value::LongArray(longs)) = blob.get("MOTION_BLOCKING") else {
+            panic!("missing heightmap")
+        };
+        assert_eq!(longs.len(), 37);
+        assert_eq!(longs[0], 1 | 128 << 9);
+        assert_eq!(longs[240 / 7], 384 << (9 * (240 % 7)));
+    }
+
+    #[test]
+    fn chunk_data() {
+        let mut chunk = Chunk::new(0, 256, 0);
+        chunk.set_block(0, 17, 0, 1);
+        assert_eq!(chunk.block(0, 17, 0), 1);
+        assert_eq!(chunk.block(0, -1, 0), AIR);
+        let data = chunk.encode(BIOME_BITS);
+        let mut section = ChunkSection::new(AIR, 0);
+        section.set_block(0, 1, 0, 1);
+        let mut expected = Vec::new();
+        ChunkSection::new(AIR, 0).encode(&mut expected, BIOME_BITS);
+        section.encode(&mut expected, BIOME_BITS);
+        for _ in 2..16 {
+            ChunkSection::new(AIR, 0).encode(&mut expected, BIOME_BITS);
+        }
+        assert_eq!(data, expected);
+    }
+}
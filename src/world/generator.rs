@@ -0,0 +1,70 @@
+use super::{blocks::BlockRegistry, Chunk, ChunkSource};
+
+/// Generates flat layers of blocks from the bottom of the world, either
+/// everywhere or as a round platform centred on 0, 0.
+pub struct FlatGenerator {
+    /// Block states from the bottom layer up.
+    layers: Vec<u32>,
+    /// Radius of the platform in blocks, or `None` for an endless world.
+    radius: Option<u32>,
+    biome: u32,
+}
+
+impl FlatGenerator {
+    pub fn new(layers: Vec<u32>, radius: Option<u32>, biome: u32) -> Self {
+        Self { layers, radius, biome }
+    }
+
+    /// Parse layers written bottom first, such as
+    /// `bedrock,2*dirt,grass_block`.
+    pub fn parse_layers(spec: &str, registry: &BlockRegistry) -> Result<Vec<u32>, String> {
+        let mut layers = Vec::new();
+        for layer in spec.split(',').map(str::trim).filter(|l| !l.is_empty()) {
+            let (count, block) = match layer.split_once('*') {
+                Some((count, block)) => (
+                    count.trim().parse::<usize>().map_err(|_| format!("invalid layer count in '{}'", layer))?,
+                    block.trim(),
+                ),
+                None => (1, layer),
+            };
+            let state = registry.state(block).ok_or_else(|| format!("unknown block '{}'", block))?;
+            layers.extend(std::iter::repeat_n(state, count));
+        }
+        Ok(layers)
+    }
+
+    /// Number of layers, and so the height of the top of the ground above
+    /// the bottom of the world.
+    pub fn height(&self) -> usize {
+        self.layers.len()
+    }
+
+    fn in_platform(&self, x: i32, z: i32) -> bool {
+        match self.radius {
+            Some(r) => {
+                let (x, z, r) = (x as i64, z as i64, r as i64);
+                x * x + z * z <= r * r
+            }
+            None => true,
+        }
+    }
+}
+
+impl ChunkSource for FlatGenerator {
+    fn load(&mut self, cx: i32, cz: i32, min_y: i32, height: u32) -> Option<Chunk> {
+        let mut chunk = Chunk::new(min_y, height, self.biome);
+        let columns: Vec<_> = (0..16usize)
+            .flat_map(|x| (0..16usize).map(move |z| (x, z)))
+            .filter(|(x, z)| self.in_platform(cx * 16 + *x as i32, cz * 16 + *z as i32))
+            .collect();
+        if columns.is_empty() {
+            return None
+        }
+        for (i, state) in self.layers.iter().enumerate() {
+            for (x, z) in &columns {
+                chunk.set_block(*x, min_y + i as i32, *z, *state);
+            }
+        }
+        Some(chunk)
+    }
+}
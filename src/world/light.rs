@@ -0,0 +1,342 @@
+use crate::protocol::data::PacketEncoder;
+
+use super::{blocks::BlockRegistry, chunk::Chunk};
+
+/// Blocks that light passes through without dimming. Names starting with
+/// `_` match the end of a block name, others match it exactly.
+const TRANSPARENT: &[&str] = &[
+    "air", "cave_air", "void_air", "barrier", "light", "structure_void",
+    "glass", "_glass", "glass_pane", "_glass_pane", "iron_bars", "chain",
+    "torch", "_torch", "lantern", "_lantern", "campfire", "_campfire",
+    "candle", "_candle", "end_rod", "lightning_rod", "beacon", "conduit",
+    "_sign", "_banner", "_button", "lever", "rail", "_rail", "_pressure_plate",
+    "tripwire", "tripwire_hook", "redstone_wire", "repeater", "comparator",
+    "daylight_detector", "_carpet", "snow", "_fence", "_fence_gate", "_wall",
+    "_slab", "_stairs", "_door", "_trapdoor", "ladder", "scaffolding", "cobweb",
+    "_bed", "chest", "trapped_chest", "ender_chest", "_head", "_skull",
+    "cake", "_cake", "flower_pot", "anvil", "_anvil", "bell", "hopper",
+    "cauldron", "_cauldron", "brewing_stand", "enchanting_table", "grindstone",
+    "stonecutter", "lectern", "spawner", "nether_portal", "end_portal",
+    "end_gateway", "fire", "soul_fire", "grass", "tall_grass", "fern",
+    "large_fern", "dead_bush", "_sapling", "dandelion", "poppy", "blue_orchid",
+    "allium", "azure_bluet", "_tulip", "oxeye_daisy", "cornflower",
+    "lily_of_the_valley", "wither_rose", "sunflower", "lilac", "rose_bush",
+    "peony", "lily_pad", "_mushroom", "_fungus", "_roots", "_sprouts", "vine",
+    "_vines", "_vines_plant", "glow_lichen", "sculk_vein", "sugar_cane",
+    "bamboo", "cactus", "wheat", "carrots", "potatoes", "beetroots",
+    "pumpkin_stem", "melon_stem", "attached_pumpkin_stem", "attached_melon_stem",
+    "cocoa", "nether_wart", "sweet_berry_bush", "kelp", "kelp_plant",
+    "seagrass", "tall_seagrass", "sea_pickle", "_coral", "_coral_fan",
+    "_coral_wall_fan", "azalea", "flowering_azalea", "spore_blossom",
+    "big_dripleaf", "big_dripleaf_stem", "small_dripleaf", "pointed_dripstone",
+    "_amethyst_bud", "amethyst_cluster", "chorus_plant", "chorus_flower",
+    "frogspawn", "sculk_sensor", "sculk_shrieker",
+];
+
+/// Blocks that dim light by one level, matched like [`TRANSPARENT`].
+const FILTERING: &[&str] = &[
+    "water", "bubble_column", "lava", "ice", "frosted_ice", "_leaves",
+    "slime_block", "honey_block",
+];
+
+fn matches(list: &[&str], name: &str) -> bool {
+    list.iter().any(|entry| match entry.starts_with('_') {
+        true => name.ends_with(entry),
+        false => name == *entry,
+    })
+}
+
+/// How much a block dims light passing through it, from 0 to 15. Blocks
+/// that aren't known to let light through are taken to be solid.
+fn opacity(name: &str, property: impl Fn(&str) -> Option<String>) -> u8 {
+    if name == "tinted_glass" || (name.ends_with("_slab") && property("type").as_deref() == Some("double")) {
+        15
+    } else if name.starts_with("potted_") || matches(TRANSPARENT, name) {
+        0
+    } else if matches(FILTERING, name) {
+        1
+    } else {
+        15
+    }
+}
+
+/// The light level a block gives off.
+fn emission(name: &str, property: impl Fn(&str) -> Option<String>) -> u8 {
+    if property("lit").as_deref() == Some("false") {
+        return 0
+    }
+    match name {
+        "light" => property("level").and_then(|l| l.parse().ok()).unwrap_or(15),
+        "candle" => 3 * property("candles").and_then(|c| c.parse().ok()).unwrap_or(1),
+        _ if name.ends_with("_candle") => 3 * property("candles").and_then(|c| c.parse().ok()).unwrap_or(1),
+        "glowstone" | "sea_lantern" | "jack_o_lantern" | "lantern" | "shroomlight"
+        | "beacon" | "conduit" | "lava" | "lava_cauldron" | "fire" | "campfire"
+        | "end_gateway" | "end_portal" | "redstone_lamp" | "ochre_froglight"
+        | "verdant_froglight" | "pearlescent_froglight" => 15,
+        "torch" | "wall_torch" | "end_rod" => 14,
+        "furnace" | "blast_furnace" | "smoker" => 13,
+        "nether_portal" => 11,
+        "soul_torch" | "soul_wall_torch" | "soul_lantern" | "soul_fire"
+        | "soul_campfire" | "crying_obsidian" => 10,
+        "redstone_ore" | "deepslate_redstone_ore" => 9,
+        "redstone_torch" | "redstone_wall_torch" | "glow_lichen" | "enchanting_table"
+        | "ender_chest" => 7,
+        "amethyst_cluster" => 5,
+        "large_amethyst_bud" => 4,
+        "magma_block" => 3,
+        "medium_amethyst_bud" => 2,
+        "small_amethyst_bud" | "brewing_stand" | "brown_mushroom" | "dragon_egg"
+        | "end_portal_frame" | "sculk_sensor" => 1,
+        _ => 0,
+    }
+}
+
+/// How each block state dims and gives off light, indexed by state ID.
+#[derive(Clone, Debug, Default)]
+pub struct LightTable {
+    opacity: Vec<u8>,
+    emission: Vec<u8>,
+}
+
+impl LightTable {
+    /// Work out the light of every state in a block registry from the
+    /// block names, using a built-in list of blocks that let light through
+    /// or give it off.
+    pub fn new(blocks: &BlockRegistry) -> Self {
+        let mut table = Self::default();
+        for (name, properties, state) in blocks.states() {
+            let name = name.strip_prefix("minecraft:").unwrap_or(name);
+            let property = |key: &str| properties.iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone());
+            let state = state as usize;
+            if state >= table.opacity.len() {
+                table.opacity.resize(state + 1, 15);
+                table.emission.resize(state + 1, 0);
+            }
+            table.opacity[state] = opacity(name, property);
+            table.emission[state] = emission(name, property).min(15);
+        }
+        table
+    }
+
+    /// How much light a state takes away, where 15 blocks it completely.
+    /// Unknown states are solid.
+    pub fn opacity(&self, state: u32) -> u8 {
+        self.opacity.get(state as usize).copied().unwrap_or(15)
+    }
+
+    pub fn emission(&self, state: u32) -> u8 {
+        self.emission.get(state as usize).copied().unwrap_or(0)
+    }
+}
+
+/// Light of a chunk as sent to the client. Light sections run from the
+/// one below the world to the one above it, so bit `i` of a mask is for
+/// chunk section `i - 1`. Sections in neither mask keep whatever light the
+/// client has; for sky light above the highest section with blocks, that
+/// is full daylight.
+#[derive(Clone, Debug, Default)]
+pub struct LightData {
+    pub sky_mask: Vec<u64>,
+    pub block_mask: Vec<u64>,
+    /// Sections with no sky light at all.
+    pub empty_sky_mask: Vec<u64>,
+    /// Sections with no block light at all.
+    pub empty_block_mask: Vec<u64>,
+    /// A 2048 byte array of nibbles for each section in `sky_mask`, from
+    /// the bottom up.
+    pub sky: Vec<Vec<u8>>,
+    pub block: Vec<Vec<u8>>,
+}
+
+impl LightData {
+    /// Work out sky and block light for a chunk. Light spreads within the
+    /// chunk only, so light from neighbouring chunks doesn't reach over the
+    /// border. Without `sky_light` (as in the nether and the end), no sky
+    /// light is sent.
+    pub fn compute(chunk: &Chunk, table: &LightTable, sky_light: bool) -> Self {
+        let sections = chunk.sections();
+        let top = sections.iter().rposition(|s| s.block_count() != 0).map_or(0, |i| i + 1);
+        // Light from the highest blocks can reach into the section above them
+        let lit = if top == 0 { 0 } else { (top + 1).min(sections.len()) };
+
+        let mut opacity = vec![0; lit * 4096];
+        let mut block = vec![0; lit * 4096];
+        let mut block_queue = Vec::new();
+        for (i, section) in sections[..lit].iter().enumerate() {
+            for j in 0..4096 {
+                let state = section.blocks.get(j);
+                opacity[i * 4096 + j] = table.opacity(state);
+                let emission = table.emission(state);
+                if emission > 0 {
+                    block[i * 4096 + j] = emission;
+                    block_queue.push(i * 4096 + j);
+                }
+            }
+        }
+        spread(&mut block, &opacity, block_queue);
+
+        let mut sky = vec![0; lit * 4096];
+        if sky_light {
+            let mut sky_queue = Vec::new();
+            for column in 0..256 {
+                let mut level = 15u8;
+                for y in (0..lit * 16).rev() {
+                    let index = y * 256 + column;
+                    level = match opacity[index] {
+                        15.. => 0,
+                        o => level.saturating_sub(o),
+                    };
+                    if level == 0 {
+                        break
+                    }
+                    sky[index] = level;
+                    sky_queue.push(index);
+                }
+            }
+            spread(&mut sky, &opacity, sky_queue);
+        }
+
+        let mut light = Self::default();
+        let words = (sections.len() + 2).div_ceil(64);
+        for mask in [&mut light.sky_mask, &mut light.block_mask, &mut light.empty_sky_mask, &mut light.empty_block_mask] {
+            *mask = vec![0; words];
+        }
+        let set = |mask: &mut Vec<u64>, section: usize| mask[(section + 1) / 64] |= 1 << ((section + 1) % 64);
+        for section in 0..sections.len() {
+            if sky_light && section < top {
+                match nibbles(&sky[section * 4096..(section + 1) * 4096]) {
+                    Some(array) => {
+                        set(&mut light.sky_mask, section);
+                        light.sky.push(array);
+                    }
+                    None => set(&mut light.empty_sky_mask, section),
+                }
+            }
+            match (section < lit).then(|| nibbles(&block[section * 4096..(section + 1) * 4096])).flatten() {
+                Some(array) => {
+                    set(&mut light.block_mask, section);
+                    light.block.push(array);
+                }
+                None => set(&mut light.empty_block_mask, section),
+            }
+        }
+        light
+    }
+
+    /// Write the light masks and arrays, as they appear at the end of the
+    /// chunk data and update light packets.
+    pub fn encode(&self, encoder: &mut impl PacketEncoder) {
+        for mask in [&self.sky_mask, &self.block_mask, &self.empty_sky_mask, &self.empty_block_mask] {
+            encoder.write_varint(mask.len() as i32);
+            for word in mask {
+                encoder.write_long(*word as i64);
+            }
+        }
+        for arrays in [&self.sky, &self.block] {
+            encoder.write_varint(arrays.len() as i32);
+            for array in arrays {
+                encoder.write_varint(array.len() as i32);
+                encoder.write_bytes(array);
+            }
+        }
+    }
+}
+
+/// Spread light out from the blocks in `queue`, losing at least one level
+/// for each block it moves, until it runs out.
+fn spread(light: &mut [u8], opacity: &[u8], mut queue: Vec<usize>) {
+    let height = light.len() / 256;
+    while let Some(index) = queue.pop() {
+        let level = light[index];
+        let (x, z, y) = (index % 16, index / 16 % 16, index / 256);
+        let neighbours = [
+            (x > 0).then(|| index - 1),
+            (x < 15).then(|| index + 1),
+            (z > 0).then(|| index - 16),
+            (z < 15).then(|| index + 16),
+            (y > 0).then(|| index - 256),
+            (y + 1 < height).then(|| index + 256),
+        ];
+        for next in neighbours.into_iter().flatten() {
+            let next_level = level.saturating_sub(opacity[next].max(1));
+            if next_level > light[next] {
+                light[next] = next_level;
+                queue.push(next);
+            }
+        }
+    }
+}
+
+/// Pack a section's light levels two to a byte, or `None` if it is dark.
+fn nibbles(levels: &[u8]) -> Option<Vec<u8>> {
+    if levels.iter().all(|l| *l == 0) {
+        return None
+    }
+    Some(levels.chunks(2).map(|pair| pair[0] | pair[1] << 4).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(array: &[u8], x: usize, y: usize, z: usize) -> u8 {
+        let index = (y * 16 + z) * 16 + x;
+        array[index / 2] >> (4 * (index % 2)) & 0xf
+    }
+
+    #[test]
+    fn empty_chunk() {
+        let table = LightTable::new(&BlockRegistry::builtin());
+        let light = LightData::compute(&Chunk::new(0, 64, 0), &table, true);
+        assert!(light.sky.is_empty() && light.block.is_empty());
+        assert_eq!(light.sky_mask, vec![0]);
+        // Every section is marked dark for block light
+        assert_eq!(light.empty_block_mask, vec![0b11110]);
+    }
+
+    #[test]
+    fn sky_light() {
+        let registry = BlockRegistry::builtin();
+        let table = LightTable::new(&registry);
+        let stone = registry.state("stone").unwrap();
+        let mut chunk = Chunk::new(0, 64, 0);
+        for x in 0..16 {
+            for z in 0..16 {
+                chunk.set_block(x, 20, z, stone);
+            }
+        }
+        // A hole in the roof lets light in, which falls off to the sides
+        chunk.set_block(8, 20, 8, 0);
+        let light = LightData::compute(&chunk, &table, true);
+        assert_eq!(light.sky_mask, vec![0b0110]);
+        assert_eq!(light.sky.len(), 2);
+        assert_eq!(level(&light.sky[1], 0, 5, 0), 15);
+        assert_eq!(level(&light.sky[1], 0, 4, 0), 0);
+        assert_eq!(level(&light.sky[1], 8, 0, 8), 15);
+        assert_eq!(level(&light.sky[0], 8, 15, 8), 15);
+        assert_eq!(level(&light.sky[0], 10, 15, 8), 13);
+
+        let light = LightData::compute(&chunk, &table, false);
+        assert!(light.sky.is_empty());
+        assert_eq!(light.sky_mask, vec![0]);
+    }
+
+    #[test]
+    fn block_light() {
+        let registry = BlockRegistry::builtin();
+        let table = LightTable::new(&registry);
+        let mut chunk = Chunk::new(0, 64, 0);
+        chunk.set_block(4, 4, 4, registry.state("lava").unwrap());
+        chunk.set_block(5, 4, 4, registry.state("stone").unwrap());
+        let light = LightData::compute(&chunk, &table, false);
+        assert_eq!(light.block_mask, vec![0b0110]);
+        assert_eq!(light.empty_block_mask, vec![0b11000]);
+        assert_eq!(level(&light.block[0], 4, 4, 4), 15);
+        assert_eq!(level(&light.block[0], 4, 7, 4), 12);
+        // Around the stone rather than through it
+        assert_eq!(level(&light.block[0], 6, 4, 4), 11);
+        assert_eq!(level(&light.block[1], 4, 0, 4), 3);
+    }
+}
@@ -0,0 +1,242 @@
+use std::{cell::RefCell, collections::{BTreeMap, HashMap, HashSet}, rc::Rc};
+
+pub mod anvil;
+pub mod blocks;
+pub mod chunk;
+pub mod generator;
+pub mod light;
+pub mod palette;
+pub mod schematic;
+
+pub use anvil::AnvilWorld;
+pub use blocks::BlockRegistry;
+pub use chunk::{Chunk, ChunkSection};
+pub use generator::FlatGenerator;
+pub use light::{LightData, LightTable};
+pub use schematic::Schematic;
+
+use palette::bits_for;
+
+/// Number of biomes in the vanilla registry codec.
+const VANILLA_BIOMES: usize = 64;
+
+/// Somewhere chunks that haven't been loaded yet can be read from.
+pub trait ChunkSource {
+    /// Load the chunk at chunk coordinates `x` and `z`, or `None` if it
+    /// doesn't exist or couldn't be read.
+    fn load(&mut self, x: i32, z: i32, min_y: i32, height: u32) -> Option<Chunk>;
+}
+
+/// The blocks of a dimension, stored as chunks that are loaded from a
+/// [`ChunkSource`] or created when a block is first set in them. Chunks
+/// that don't exist are empty.
+pub struct World {
+    dimension_type: String,
+    min_y: i32,
+    height: u32,
+    spawn: (i32, i32, i32),
+    biome: u32,
+    /// Bits per biome ID in chunk data
+    biome_bits: u8,
+    light: Rc<LightTable>,
+    sky_light: bool,
+    /// Light of chunks that have been sent, until they change
+    light_cache: RefCell<HashMap<(i32, i32), LightData>>,
+    chunks: HashMap<(i32, i32), Chunk>,
+    source: Option<Box<dyn ChunkSource>>,
+    /// Chunks the source doesn't have, so it isn't asked again.
+    missing: HashSet<(i32, i32)>,
+}
+
+impl World {
+    /// An empty world using a dimension type from the registry codec, which
+    /// must start at `min_y` and be `height` blocks tall.
+    pub fn new(dimension_type: &str, min_y: i32, height: u32) -> Self {
+        Self {
+            dimension_type: dimension_type.to_owned(),
+            min_y,
+            height,
+            spawn: (0, 64, 0),
+            biome: 0,
+            biome_bits: bits_for(VANILLA_BIOMES),
+            light: Rc::new(LightTable::new(&BlockRegistry::builtin())),
+            sky_light: true,
+            light_cache: RefCell::new(HashMap::new()),
+            chunks: HashMap::new(),
+            source: None,
+            missing: HashSet::new(),
+        }
+    }
+
+    /// Load chunks that haven't been set from `source` when they're needed.
+    pub fn with_source(mut self, source: impl ChunkSource + 'static) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    pub fn dimension_type(&self) -> &str {
+        &self.dimension_type
+    }
+
+    pub fn min_y(&self) -> i32 {
+        self.min_y
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The block players spawn at.
+    pub fn spawn(&self) -> (i32, i32, i32) {
+        self.spawn
+    }
+
+    pub fn set_spawn(&mut self, x: i32, y: i32, z: i32) {
+        self.spawn = (x, y, z);
+    }
+
+    /// The biome of chunks that are created rather than loaded.
+    pub fn set_biome(&mut self, biome: u32) {
+        self.biome = biome;
+    }
+
+    /// Set the number of biomes in the registry codec, which decides how
+    /// biomes are encoded in chunk data.
+    pub fn set_biome_count(&mut self, count: usize) {
+        self.biome_bits = bits_for(count);
+    }
+
+    /// The `chunk_data` buffer of the chunk data packet for a chunk of
+    /// this world.
+    pub fn chunk_data(&self, chunk: &Chunk) -> Vec<u8> {
+        chunk.encode(self.biome_bits)
+    }
+
+    /// Light chunks using `table`, which must match the block states in
+    /// the world, and with sky light only if `sky_light` is set.
+    pub fn set_lighting(&mut self, table: Rc<LightTable>, sky_light: bool) {
+        self.light = table;
+        self.sky_light = sky_light;
+        self.light_cache.get_mut().clear();
+    }
+
+    /// The sky and block light of the chunk at chunk coordinates `x` and
+    /// `z`, or of an empty chunk if it hasn't been loaded. Light is cached
+    /// until the chunk is changed.
+    pub fn chunk_light(&self, x: i32, z: i32) -> LightData {
+        self.light_cache.borrow_mut().entry((x, z))
+            .or_insert_with(|| match self.chunk(x, z) {
+                Some(chunk) => LightData::compute(chunk, &self.light, self.sky_light),
+                None => LightData::compute(&self.empty_chunk(), &self.light, self.sky_light),
+            })
+            .clone()
+    }
+
+    /// An empty chunk, as sent for chunks that don't exist.
+    pub fn empty_chunk(&self) -> Chunk {
+        Chunk::new(self.min_y, self.height, self.biome)
+    }
+
+    /// A chunk that has already been loaded or created.
+    pub fn chunk(&self, x: i32, z: i32) -> Option<&Chunk> {
+        self.chunks.get(&(x, z))
+    }
+
+    /// A chunk, loading it from the source if it hasn't been loaded yet.
+    pub fn load_chunk(&mut self, x: i32, z: i32) -> Option<&Chunk> {
+        if !self.chunks.contains_key(&(x, z)) && !self.missing.contains(&(x, z)) {
+            match self.source.as_mut().and_then(|s| s.load(x, z, self.min_y, self.height)) {
+                Some(chunk) => {
+                    self.chunks.insert((x, z), chunk);
+                    self.light_cache.get_mut().remove(&(x, z));
+                }
+                None => { self.missing.insert((x, z)); }
+            }
+        }
+        self.chunks.get(&(x, z))
+    }
+
+    /// The chunk at chunk coordinates `x` and `z`, loaded or created empty
+    /// if needed. Its cached light is dropped, since it may be changed.
+    pub fn chunk_mut(&mut self, x: i32, z: i32) -> &mut Chunk {
+        self.load_chunk(x, z);
+        self.light_cache.get_mut().remove(&(x, z));
+        let empty = self.empty_chunk();
+        self.chunks.entry((x, z)).or_insert(empty)
+    }
+
+    /// Positions of every chunk that has been loaded or created.
+    pub fn chunk_positions(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.chunks.keys().copied()
+    }
+
+    pub fn block(&self, x: i32, y: i32, z: i32) -> u32 {
+        match self.chunk(x.div_euclid(16), z.div_euclid(16)) {
+            Some(chunk) => chunk.block(x.rem_euclid(16) as usize, y, z.rem_euclid(16) as usize),
+            None => chunk::AIR,
+        }
+    }
+
+    /// Set a block, returning false if `y` is outside the world.
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, state: u32) -> bool {
+        if y < self.min_y || y >= self.min_y + self.height as i32 {
+            return false
+        }
+        self.chunk_mut(x.div_euclid(16), z.div_euclid(16))
+            .set_block(x.rem_euclid(16) as usize, y, z.rem_euclid(16) as usize, state)
+    }
+}
+
+pub type SharedWorlds = Rc<RefCell<WorldRegistry>>;
+
+/// The worlds of the server, by name. The first world added is the one
+/// players join.
+#[derive(Default)]
+pub struct WorldRegistry {
+    worlds: BTreeMap<String, World>,
+    default: Option<String>,
+    blocks: BlockRegistry,
+}
+
+impl WorldRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn shared() -> SharedWorlds {
+        Rc::new(RefCell::new(Self::new()))
+    }
+
+    /// Add a world, replacing any world with the same name.
+    pub fn insert(&mut self, name: &str, world: World) {
+        self.default.get_or_insert_with(|| name.to_owned());
+        self.worlds.insert(name.to_owned(), world);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&World> {
+        self.worlds.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut World> {
+        self.worlds.get_mut(name)
+    }
+
+    /// Names of every world, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.worlds.keys().map(String::as_str)
+    }
+
+    /// The block states of every world.
+    pub fn blocks(&self) -> &BlockRegistry {
+        &self.blocks
+    }
+
+    pub fn set_blocks(&mut self, blocks: BlockRegistry) {
+        self.blocks = blocks;
+    }
+
+    /// Name of the world players join.
+    pub fn default_world(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+}
@@ -0,0 +1,195 @@
+use crate::protocol::data::PacketEncoder;
+
+/// Palette sizes for one kind of paletted container.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaletteKind {
+    /// Number of entries in the container.
+    pub size: usize,
+    /// Smallest number of bits used for an indirect palette.
+    pub min_bits: u8,
+    /// Largest number of bits used for an indirect palette, above which
+    /// values are stored directly.
+    pub max_indirect_bits: u8,
+}
+
+impl PaletteKind {
+    /// Block states, 16x16x16 per section.
+    pub const BLOCK_STATES: PaletteKind = PaletteKind { size: 4096, min_bits: 4, max_indirect_bits: 8 };
+    /// Biomes, 4x4x4 per section.
+    pub const BIOMES: PaletteKind = PaletteKind { size: 64, min_bits: 1, max_indirect_bits: 3 };
+}
+
+#[derive(Clone, Debug)]
+enum Storage {
+    Single(u32),
+    Indexed { palette: Vec<u32>, indices: Vec<u16> },
+}
+
+/// A fixed-size array of values (block states or biomes), stored as a
+/// palette of the distinct values and an index into it for each entry.
+#[derive(Clone, Debug)]
+pub struct PalettedContainer {
+    kind: PaletteKind,
+    storage: Storage,
+}
+
+impl PalettedContainer {
+    /// A container with every entry set to `value`.
+    pub fn new(kind: PaletteKind, value: u32) -> Self {
+        Self { kind, storage: Storage::Single(value) }
+    }
+
+    /// A container from a palette and an index into it for each entry.
+    pub fn from_palette(kind: PaletteKind, palette: Vec<u32>, indices: Vec<u16>) -> Self {
+        assert_eq!(indices.len(), kind.size, "wrong number of entries");
+        let storage = match palette.len() {
+            1 => Storage::Single(palette[0]),
+            _ => Storage::Indexed { palette, indices },
+        };
+        Self { kind, storage }
+    }
+
+    pub fn kind(&self) -> PaletteKind {
+        self.kind
+    }
+
+    pub fn get(&self, index: usize) -> u32 {
+        match &self.storage {
+            Storage::Single(value) => *value,
+            Storage::Indexed { palette, indices } => palette[indices[index] as usize],
+        }
+    }
+
+    pub fn set(&mut self, index: usize, value: u32) {
+        assert!(index < self.kind.size, "index {} out of range", index);
+        let size = self.kind.size;
+        match &mut self.storage {
+            Storage::Single(current) if *current == value => (),
+            Storage::Single(current) => {
+                let mut indices = vec![0; size];
+                indices[index] = 1;
+                self.storage = Storage::Indexed { palette: vec![*current, value], indices };
+            }
+            Storage::Indexed { palette, indices } => {
+                let i = match palette.iter().position(|v| *v == value) {
+                    Some(i) => i,
+                    None => {
+                        palette.push(value);
+                        palette.len() - 1
+                    }
+                };
+                indices[index] = i as u16;
+            }
+        }
+    }
+
+    /// Set every entry to `value`.
+    pub fn fill(&mut self, value: u32) {
+        self.storage = Storage::Single(value);
+    }
+
+    /// Number of entries that aren't `value`.
+    pub fn count_not(&self, value: u32) -> usize {
+        match &self.storage {
+            Storage::Single(v) if *v == value => 0,
+            Storage::Single(_) => self.kind.size,
+            Storage::Indexed { palette, indices } => indices.iter()
+                .filter(|i| palette[**i as usize] != value)
+                .count(),
+        }
+    }
+
+    /// Drop palette entries that are no longer used, going back to a single
+    /// value if only one is left.
+    pub fn compact(&mut self) {
+        let Storage::Indexed { palette, indices } = &mut self.storage else {
+            return
+        };
+        let mut used = vec![false; palette.len()];
+        for i in indices.iter() {
+            used[*i as usize] = true;
+        }
+        if used.iter().all(|u| *u) && palette.len() > 1 {
+            return
+        }
+        let mut remap = vec![0u16; palette.len()];
+        let mut new_palette = Vec::new();
+        for (i, value) in palette.iter().enumerate() {
+            if used[i] {
+                remap[i] = new_palette.len() as u16;
+                new_palette.push(*value);
+            }
+        }
+        if new_palette.len() == 1 {
+            self.storage = Storage::Single(new_palette[0]);
+            return
+        }
+        for i in indices.iter_mut() {
+            *i = remap[*i as usize];
+        }
+        *palette = new_palette;
+    }
+
+    /// Write the container in the network format: bits per entry, the
+    /// palette, then the packed data array. Values stored directly take
+    /// `direct_bits` each, which the client works out from the size of the
+    /// registry the values come from.
+    pub fn encode(&self, encoder: &mut impl PacketEncoder, direct_bits: u8) {
+        match &self.storage {
+            Storage::Single(value) => {
+                encoder.write_ubyte(0);
+                encoder.write_varint(*value as i32);
+                encoder.write_varint(0);
+            }
+            Storage::Indexed { palette, indices } => {
+                let bits = bits_for(palette.len()).max(self.kind.min_bits);
+                if bits <= self.kind.max_indirect_bits {
+                    encoder.write_ubyte(bits);
+                    encoder.write_varint(palette.len() as i32);
+                    for value in palette {
+                        encoder.write_varint(*value as i32);
+                    }
+                    write_longs(encoder, &pack(indices.iter().map(|i| *i as u64), bits));
+                } else {
+                    let bits = direct_bits;
+                    encoder.write_ubyte(bits);
+                    let values = indices.iter().map(|i| palette[*i as usize] as u64);
+                    write_longs(encoder, &pack(values, bits));
+                }
+            }
+        }
+    }
+}
+
+/// Number of bits needed to store indices into a palette of `len` values.
+pub fn bits_for(len: usize) -> u8 {
+    if len <= 1 { 0 } else { (usize::BITS - (len - 1).leading_zeros()) as u8 }
+}
+
+/// Pack values into longs, `bits` at a time starting from the least
+/// significant bit. Values never span two longs; leftover bits are zero.
+pub fn pack(values: impl ExactSizeIterator<Item = u64>, bits: u8) -> Vec<i64> {
+    let per_long = 64 / bits as usize;
+    let mut longs = vec![0u64; values.len().div_ceil(per_long)];
+    for (i, value) in values.enumerate() {
+        longs[i / per_long] |= value << ((i % per_long) * bits as usize);
+    }
+    longs.into_iter().map(|l| l as i64).collect()
+}
+
+/// Unpack `count` values of `bits` each, the reverse of [`pack`].
+pub fn unpack(longs: &[i64], bits: u8, count: usize) -> Vec<u64> {
+    let per_long = 64 / bits as usize;
+    let mask = (1u64 << bits) - 1;
+    (0..count).map(|i| match longs.get(i / per_long) {
+        Some(long) => (*long as u64 >> ((i % per_long) * bits as usize)) & mask,
+        None => 0,
+    }).collect()
+}
+
+fn write_longs(encoder: &mut impl PacketEncoder, longs: &[i64]) {
+    encoder.write_varint(longs.len() as i32);
+    for long in longs {
+        encoder.write_long(*long);
+    }
+}
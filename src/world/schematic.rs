@@ -0,0 +1,245 @@
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+
+use log::{debug, warn};
+use nbt::{Blob, Value};
+
+use super::{blocks::BlockRegistry, chunk::AIR, World};
+
+/// A structure loaded from a Sponge schematic (`.schem`, version 2 or 3).
+pub struct Schematic {
+    pub width: u16,
+    pub height: u16,
+    pub length: u16,
+    /// Block states, indexed by `(y * length + z) * width + x`.
+    blocks: Vec<u32>,
+}
+
+type Compound = nbt::Map<String, Value>;
+
+fn get<'a>(tag: &'a Compound, key: &str) -> Result<&'a Value, String> {
+    tag.get(key).ok_or_else(|| format!("missing {}", key))
+}
+
+fn get_short(tag: &Compound, key: &str) -> Result<u16, String> {
+    match get(tag, key)? {
+        Value::Short(v) => Ok(*v as u16),
+        _ => Err(format!("{} should be a short", key)),
+    }
+}
+
+fn get_compound<'a>(tag: &'a Compound, key: &str) -> Result<&'a Compound, String> {
+    match get(tag, key)? {
+        Value::Compound(v) => Ok(v),
+        _ => Err(format!("{} should be a compound", key)),
+    }
+}
+
+fn get_bytes<'a>(tag: &'a Compound, key: &str) -> Result<&'a [i8], String> {
+    match get(tag, key)? {
+        Value::ByteArray(v) => Ok(v),
+        _ => Err(format!("{} should be a byte array", key)),
+    }
+}
+
+impl Schematic {
+    /// Load a gzipped schematic, mapping its palette to block states with
+    /// `registry`. Blocks that aren't in the registry are replaced with air.
+    pub fn load(path: impl AsRef<Path>, registry: &BlockRegistry) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let err = |e: &dyn std::fmt::Display| format!("couldn't load schematic {}: {}", path.display(), e);
+        let file = File::open(path).map_err(|e| err(&e))?;
+        let blob = Blob::from_gzip_reader(&mut BufReader::new(file)).map_err(|e| err(&e))?;
+        let (schematic, unknown) = Self::from_nbt(&blob, registry).map_err(|e| err(&e))?;
+        if !unknown.is_empty() {
+            warn!(
+                "Replaced unknown palette entries in schematic {} with air: {} in total, e.g. {}. Check that block_report is the 1.19.3 blocks.json",
+                path.display(), unknown.len(), unknown[..unknown.len().min(3)].join(", "),
+            );
+        }
+        Ok(schematic)
+    }
+
+    /// Read a schematic, also returning the palette entries that aren't in
+    /// `registry`.
+    fn from_nbt(blob: &Blob, registry: &BlockRegistry) -> Result<(Self, Vec<String>), String> {
+        // Version 3 wraps everything in a "Schematic" compound, version 2
+        // uses the root compound directly.
+        let mut root = Compound::new();
+        match blob.get("Schematic") {
+            Some(Value::Compound(tag)) => root.clone_from(tag),
+            _ => for key in ["Version", "Width", "Height", "Length", "Palette", "BlockData"] {
+                if let Some(value) = blob.get(key) {
+                    root.insert(key.to_owned(), value.clone());
+                }
+            },
+        }
+        let version = match get(&root, "Version")? {
+            Value::Int(v) => *v,
+            _ => return Err("Version should be an int".to_owned()),
+        };
+        let (palette, data) = match version {
+            2 => (get_compound(&root, "Palette")?, get_bytes(&root, "BlockData")?),
+            3 => {
+                let blocks = get_compound(&root, "Blocks")?;
+                (get_compound(blocks, "Palette")?, get_bytes(blocks, "Data")?)
+            }
+            v => return Err(format!("unsupported schematic version {}", v)),
+        };
+        let (width, height, length) = (get_short(&root, "Width")?, get_short(&root, "Height")?, get_short(&root, "Length")?);
+
+        let mut states = HashMap::new();
+        let mut unknown = Vec::new();
+        for (name, index) in palette {
+            let Value::Int(index) = index else {
+                return Err(format!("palette entry {} should be an int", name))
+            };
+            let state = registry.state(name).unwrap_or_else(|| {
+                debug!("Unknown block {} in schematic, using air", name);
+                unknown.push(name.clone());
+                AIR
+            });
+            states.insert(*index, state);
+        }
+        unknown.sort();
+
+        let size = width as usize * height as usize * length as usize;
+        let mut blocks = Vec::with_capacity(size);
+        let mut bytes = data.iter().map(|b| *b as u8);
+        while blocks.len() < size {
+            let mut index = 0i32;
+            for i in 0.. {
+                let byte = bytes.next().ok_or("block data is too short")?;
+                index |= ((byte & 0x7f) as i32) << (7 * i);
+                if byte & 0x80 == 0 {
+                    break
+                }
+                if i == 4 {
+                    return Err("block data contains an invalid varint".to_owned())
+                }
+            }
+            blocks.push(*states.get(&index).ok_or_else(|| format!("block data refers to unknown palette index {}", index))?);
+        }
+        Ok((Self { width, height, length, blocks }, unknown))
+    }
+
+    pub fn block(&self, x: u16, y: u16, z: u16) -> u32 {
+        self.blocks[(y as usize * self.length as usize + z as usize) * self.width as usize + x as usize]
+    }
+
+    /// Place the schematic in a world with its minimum corner at `offset`.
+    /// Air in the schematic is skipped, so it doesn't clear existing blocks.
+    pub fn place(&self, world: &mut World, offset: [i32; 3]) {
+        for y in 0..self.height {
+            for z in 0..self.length {
+                for x in 0..self.width {
+                    let state = self.block(x, y, z);
+                    if state != AIR {
+                        world.set_block(offset[0] + x as i32, offset[1] + y as i32, offset[2] + z as i32, state);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x1x2 palette of more than 128 entries, so indices past 127 take
+    /// two bytes, and block data with stone at index 200 and dirt at 1.
+    fn palette_and_data() -> (Compound, Vec<i8>) {
+        let mut palette = Compound::new();
+        for i in 0..256 {
+            let name = match i {
+                1 => "minecraft:dirt".to_owned(),
+                200 => "minecraft:stone".to_owned(),
+                _ => format!("test:block_{}", i),
+            };
+            palette.insert(name, Value::Int(i));
+        }
+        // indices 200, 1, 0, 255 in x, z order
+        let data = [0xc8u8, 0x01, 0x01, 0x00, 0xff, 0x01].map(|b| b as i8).to_vec();
+        (palette, data)
+    }
+
+    fn dimensions(tag: &mut Compound, version: i32) {
+        tag.insert("Version".to_owned(), Value::Int(version));
+        tag.insert("Width".to_owned(), Value::Short(2));
+        tag.insert("Height".to_owned(), Value::Short(1));
+        tag.insert("Length".to_owned(), Value::Short(2));
+    }
+
+    fn check(schematic: &Schematic, unknown: &[String]) {
+        let registry = BlockRegistry::builtin();
+        assert_eq!((schematic.width, schematic.height, schematic.length), (2, 1, 2));
+        assert_eq!(schematic.block(0, 0, 0), registry.state("stone").unwrap());
+        assert_eq!(schematic.block(1, 0, 0), registry.state("dirt").unwrap());
+        assert_eq!(schematic.block(0, 0, 1), AIR);
+        assert_eq!(schematic.block(1, 0, 1), AIR);
+        assert_eq!(unknown.len(), 254);
+    }
+
+    #[test]
+    fn version_2() {
+        let (palette, data) = palette_and_data();
+        let mut blob = Blob::new();
+        let mut root = Compound::new();
+        dimensions(&mut root, 2);
+        root.insert("Palette".to_owned(), Value::Compound(palette));
+        root.insert("BlockData".to_owned(), Value::ByteArray(data));
+        for (key, value) in root {
+            blob.insert(key, value).unwrap();
+        }
+        let (schematic, unknown) = Schematic::from_nbt(&blob, &BlockRegistry::builtin()).unwrap();
+        check(&schematic, &unknown);
+    }
+
+    #[test]
+    fn version_3() {
+        let (palette, data) = palette_and_data();
+        let mut blocks = Compound::new();
+        blocks.insert("Palette".to_owned(), Value::Compound(palette));
+        blocks.insert("Data".to_owned(), Value::ByteArray(data));
+        let mut root = Compound::new();
+        dimensions(&mut root, 3);
+        root.insert("Blocks".to_owned(), Value::Compound(blocks));
+        let mut blob = Blob::new();
+        blob.insert("Schematic", Value::Compound(root)).unwrap();
+        let (schematic, unknown) = Schematic::from_nbt(&blob, &BlockRegistry::builtin()).unwrap();
+        check(&schematic, &unknown);
+    }
+
+    #[test]
+    fn bad_block_data() {
+        let registry = BlockRegistry::builtin();
+        // too short, palette index 300 (not in the palette) and a varint over 5 bytes
+        for data in [vec![0xc8u8, 0x01], vec![0xac, 0x02, 0, 0, 0], vec![0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0, 0]] {
+            let (palette, _) = palette_and_data();
+            let mut blob = Blob::new();
+            let mut root = Compound::new();
+            dimensions(&mut root, 2);
+            root.insert("Palette".to_owned(), Value::Compound(palette));
+            root.insert("BlockData".to_owned(), Value::ByteArray(data.iter().map(|b| *b as i8).collect()));
+            for (key, value) in root {
+                blob.insert(key, value).unwrap();
+            }
+            assert!(Schematic::from_nbt(&blob, &registry).is_err(), "{:?} was accepted", data);
+        }
+    }
+
+    #[test]
+    fn place_at_offset() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.state("stone").unwrap();
+        let schematic = Schematic { width: 2, height: 1, length: 1, blocks: vec![stone, AIR] };
+        let mut world = World::new("minecraft:overworld", -64, 384);
+        let dirt = registry.state("dirt").unwrap();
+        world.set_block(-9, 70, 21, dirt);
+        schematic.place(&mut world, [-10, 70, 21]);
+        assert_eq!(world.block(-10, 70, 21), stone);
+        // air in the schematic leaves the block that was there
+        assert_eq!(world.block(-9, 70, 21), dirt);
+        assert_eq!(world.block(0, 0, 0), AIR);
+    }
+}